@@ -0,0 +1,186 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+use serde::{Deserialize, Serialize};
+
+// ---------- FIXED POINT PRICE AND QUANTITY ----------
+
+/// Errors returned while converting a decimal string into a fixed point value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalError {
+    Empty,        // nothing to parse.
+    InvalidDigit, // the string is not a plain decimal number.
+    TooPrecise,   // the number has more significant decimals than the scale allows.
+    Overflow,     // the number does not fit in the underlying integer.
+    Negative,     // a negative number was given for an unsigned value.
+}
+
+impl Display for DecimalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            DecimalError::Empty => "empty decimal string",
+            DecimalError::InvalidDigit => "invalid digit in decimal string",
+            DecimalError::TooPrecise => "decimal has more digits than the scale allows",
+            DecimalError::Overflow => "decimal is out of range",
+            DecimalError::Negative => "decimal can't be negative",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+/// Parses a decimal string into an integer count of `10^-scale` units, without any rounding.
+fn parse_decimal(s: &str, scale: u32) -> Result<i128, DecimalError> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err(DecimalError::Empty);
+    }
+    if !int_part
+        .bytes()
+        .chain(frac_part.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(DecimalError::InvalidDigit);
+    }
+
+    // the digits after the scale must all be zeros, otherwise the value would be rounded.
+    let frac_part = frac_part.trim_end_matches('0');
+    if frac_part.len() > scale as usize {
+        return Err(DecimalError::TooPrecise);
+    }
+
+    let mut value: i128 = 0;
+    for b in int_part.bytes().chain(frac_part.bytes()) {
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add((b - b'0') as i128))
+            .ok_or(DecimalError::Overflow)?;
+    }
+    let padding = 10i128
+        .checked_pow(scale - frac_part.len() as u32)
+        .ok_or(DecimalError::Overflow)?;
+    let value = value.checked_mul(padding).ok_or(DecimalError::Overflow)?;
+
+    Ok(if negative { -value } else { value })
+}
+
+/// Formats an integer count of `10^-scale` units as a decimal string with exactly `scale` decimals.
+fn format_decimal(value: i128, scale: u32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let digits = value.unsigned_abs().to_string();
+    if scale == 0 {
+        return format!("{sign}{digits}");
+    }
+    let digits = format!("{:0>width$}", digits, width = scale as usize + 1);
+    let (int_part, frac_part) = digits.split_at(digits.len() - scale as usize);
+    format!("{sign}{int_part}.{frac_part}")
+}
+
+/// A price expressed as an integer number of `10^-scale` units, where the scale is defined
+/// per instrument. Integer prices make every price level exact, `100.10` and `100.1` are the
+/// same level.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Price(pub i64);
+
+impl Price {
+    /// Converts a decimal string into a price with the given scale.
+    /// ```rust
+    /// use core_utils::{DecimalError, Price};
+    ///
+    /// assert_eq!(Price::from_decimal("100.10", 2), Ok(Price(10010)));
+    /// assert_eq!(Price::from_decimal("100.1", 2), Ok(Price(10010)));
+    /// assert_eq!(Price::from_decimal("100.105", 2), Err(DecimalError::TooPrecise));
+    /// ```
+    pub fn from_decimal(s: &str, scale: u32) -> Result<Price, DecimalError> {
+        let value = parse_decimal(s, scale)?;
+        i64::try_from(value)
+            .map(Price)
+            .map_err(|_| DecimalError::Overflow)
+    }
+
+    /// Converts the price into a decimal string with the given scale.
+    /// ```rust
+    /// assert_eq!(core_utils::Price(10010).to_decimal(2), "100.10");
+    /// assert_eq!(core_utils::Price(-5).to_decimal(2), "-0.05");
+    /// ```
+    pub fn to_decimal(&self, scale: u32) -> String {
+        format_decimal(self.0 as i128, scale)
+    }
+}
+
+/// A quantity expressed as an integer number of `10^-scale` units, where the scale is defined
+/// per instrument.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Qty(pub u64);
+
+impl Qty {
+    pub const ZERO: Qty = Qty(0);
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Converts a decimal string into a quantity with the given scale.
+    /// ```rust
+    /// use core_utils::{DecimalError, Qty};
+    ///
+    /// assert_eq!(Qty::from_decimal("0.25", 3), Ok(Qty(250)));
+    /// assert_eq!(Qty::from_decimal("-1", 3), Err(DecimalError::Negative));
+    /// ```
+    pub fn from_decimal(s: &str, scale: u32) -> Result<Qty, DecimalError> {
+        let value = parse_decimal(s, scale)?;
+        if value < 0 {
+            return Err(DecimalError::Negative);
+        }
+        u64::try_from(value)
+            .map(Qty)
+            .map_err(|_| DecimalError::Overflow)
+    }
+
+    /// Converts the quantity into a decimal string with the given scale.
+    /// ```rust
+    /// assert_eq!(core_utils::Qty(250).to_decimal(3), "0.250");
+    /// ```
+    pub fn to_decimal(&self, scale: u32) -> String {
+        format_decimal(self.0 as i128, scale)
+    }
+}
+
+impl Add for Qty {
+    type Output = Qty;
+
+    fn add(self, rhs: Qty) -> Qty {
+        Qty(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Qty {
+    fn add_assign(&mut self, rhs: Qty) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Qty {
+    type Output = Qty;
+
+    fn sub(self, rhs: Qty) -> Qty {
+        Qty(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Qty {
+    fn sub_assign(&mut self, rhs: Qty) {
+        self.0 -= rhs.0;
+    }
+}
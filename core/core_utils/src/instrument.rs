@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, DecimalError, OrderType, Price, Qty, RawOrder, RejectReason};
+
+// ---------- INSTRUMENT REFERENCE DATA ----------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradingStatus {
+    TRADING, // orders are accepted and matched.
+    HALTED,  // trading is paused, only cancels are accepted.
+    CLOSED,  // the instrument is not trading, only cancels are accepted.
+}
+
+/// The static definition of a tradable instrument, every price and quantity of an order
+/// must be a multiple of the tick and lot size and must lie within the configured range.
+/// Prices are expressed with `price_scale` decimals and quantities with `qty_scale` decimals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub price_scale: u32,
+    pub qty_scale: u32,
+    pub tick_size: Price,
+    pub lot_size: Qty,
+    pub min_price: Price,
+    pub max_price: Price,
+    pub min_size: Qty,
+    pub max_size: Qty,
+    pub status: TradingStatus,
+}
+
+impl Instrument {
+    /// Creates an instrument which accepts any positive price and size, tick and lot
+    /// sizes are a single unit.
+    pub fn new(symbol: &str) -> Self {
+        Instrument {
+            symbol: symbol.to_string(),
+            price_scale: 0,
+            qty_scale: 0,
+            tick_size: Price(1),
+            lot_size: Qty(1),
+            min_price: Price(1),
+            max_price: Price(i64::MAX),
+            min_size: Qty(1),
+            max_size: Qty(u64::MAX),
+            status: TradingStatus::TRADING,
+        }
+    }
+
+    /// Loads every instrument definition from a json file, this is done once at startup.
+    /// A definition that fails `check` is refused with an `InvalidData` error.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Instrument>> {
+        let reader = BufReader::new(File::open(path)?);
+        let instruments: Vec<Instrument> = serde_json::from_reader(reader)?;
+        for instrument in &instruments {
+            instrument.check().map_err(|reason| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: {reason}", instrument.symbol),
+                )
+            })?;
+        }
+        Ok(instruments)
+    }
+
+    /// Checks that the definition can validate orders, the tick and lot sizes must be
+    /// positive and neither the price nor the size range may be empty.
+    /// ```rust
+    /// use core_utils::{Instrument, Price, Qty};
+    ///
+    /// let mut instrument = Instrument::new("BTCINR");
+    /// assert!(instrument.check().is_ok());
+    ///
+    /// instrument.tick_size = Price(0);
+    /// assert!(instrument.check().is_err());
+    ///
+    /// instrument.tick_size = Price(1);
+    /// instrument.min_size = Qty(10);
+    /// instrument.max_size = Qty(5);
+    /// assert!(instrument.check().is_err());
+    /// ```
+    pub fn check(&self) -> Result<(), String> {
+        if self.tick_size.0 <= 0 {
+            return Err(format!(
+                "tick size must be positive, got {}",
+                self.tick_size.0
+            ));
+        }
+        if self.lot_size.is_zero() {
+            return Err("lot size must be positive, got 0".into());
+        }
+        if self.min_price > self.max_price {
+            return Err(format!(
+                "min price {} is above max price {}",
+                self.min_price.0, self.max_price.0
+            ));
+        }
+        if self.min_size > self.max_size {
+            return Err(format!(
+                "min size {} is above max size {}",
+                self.min_size.0, self.max_size.0
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn parse_price(&self, s: &str) -> Result<Price, DecimalError> {
+        Price::from_decimal(s, self.price_scale)
+    }
+
+    pub fn parse_qty(&self, s: &str) -> Result<Qty, DecimalError> {
+        Qty::from_decimal(s, self.qty_scale)
+    }
+
+    /// Checks the order against the reference data of the instrument.
+    /// ```rust
+    /// use core_utils::{Instrument, Price, Qty, RawOrder, RejectReason};
+    ///
+    /// let mut instrument = Instrument::new("BTCINR");
+    /// instrument.tick_size = Price(5);
+    /// instrument.lot_size = Qty(10);
+    ///
+    /// let order = RawOrder::default().with_price(Price(100)).with_size(Qty(20)).to_owned();
+    /// assert_eq!(instrument.validate(&order), Ok(()));
+    ///
+    /// let order = RawOrder::default().with_price(Price(101)).with_size(Qty(20)).to_owned();
+    /// assert_eq!(instrument.validate(&order), Err(RejectReason::OFF_TICK));
+    ///
+    /// let order = RawOrder::default().with_price(Price(100)).with_size(Qty(25)).to_owned();
+    /// assert_eq!(instrument.validate(&order), Err(RejectReason::OFF_LOT));
+    /// ```
+    pub fn validate(&self, order: &RawOrder) -> Result<(), RejectReason> {
+        // a cancel only takes liquidity out of the book, so it is always accepted.
+        if order.command == Command::CANCEL {
+            return Ok(());
+        }
+
+        if self.status != TradingStatus::TRADING {
+            return Err(RejectReason::HALTED);
+        }
+
+        if order.size.is_zero() {
+            return Err(RejectReason::INVALID_SIZE);
+        }
+        if !order.size.0.is_multiple_of(self.lot_size.0) {
+            return Err(RejectReason::OFF_LOT);
+        }
+        if order.size < self.min_size || order.size > self.max_size {
+            return Err(RejectReason::SIZE_OUT_OF_RANGE);
+        }
+        // the peak of an iceberg order is traded like an order of its own.
+        if let Some(display_size) = order.display_size {
+            if display_size.is_zero() {
+                return Err(RejectReason::INVALID_SIZE);
+            }
+            if !display_size.0.is_multiple_of(self.lot_size.0) {
+                return Err(RejectReason::OFF_LOT);
+            }
+        }
+
+        // a market order has no price of its own, only its protection price is checked.
+        let price = match (order.command, order.order_type) {
+            (Command::NEW, OrderType::MARKET) => order.protection_price,
+            _ => Some(order.price),
+        };
+        for price in [price, order.stop_price].into_iter().flatten() {
+            if price.0 % self.tick_size.0 != 0 {
+                return Err(RejectReason::OFF_TICK);
+            }
+            if !self.price_in_range(price) {
+                return Err(RejectReason::PRICE_OUT_OF_RANGE);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn price_in_range(&self, price: Price) -> bool {
+        self.min_price <= price && price <= self.max_price
+    }
+}
@@ -1,8 +1,18 @@
+pub mod fixed;
+pub mod instrument;
+pub mod market_data;
+pub mod snapshot;
+
 use serde::{Deserialize, Serialize};
 
+pub use fixed::{DecimalError, Price, Qty};
+pub use instrument::{Instrument, TradingStatus};
+pub use market_data::{L2Book, MarketData, MarketDataMessage, SequenceGap};
+pub use snapshot::{L2Snapshot, L3Order, L3Snapshot, Level};
+
 // ---------- ORDER BOOK JARGONS ----------
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Side {
     ASK,
     BID,
@@ -14,6 +24,45 @@ pub enum OrderType {
     MARKET,
 }
 
+/// How long an order stays active in the order book, every timestamp is
+/// expressed in nanoseconds since the unix epoch as stamped by the sequencer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    GTC,      // good till cancelled
+    IOC,      // immediate or cancel, the unfilled size is never rested.
+    FOK,      // fill or kill, the order is either filled completely or not at all.
+    DAY,      // expires at the end of the trading session.
+    GTD(u64), // good till date, expires at the given timestamp.
+}
+
+/// What the sequenced message asks the matching engine to do with the order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    NEW,    // a new order to be matched or inserted in the book.
+    CANCEL, // cancel the resting order with the same order id.
+    AMEND,  // replace the price and the remaining size of the resting order with the same order id.
+}
+
+/// How a post-only order is handled when it would take liquidity on arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostOnly {
+    REJECT, // the order is rejected.
+    SLIDE,  // the order is repriced one tick away from the best opposite price and rests.
+}
+
+/// What the matching engine does when an incoming order would trade against a resting order
+/// of the same owner, the mode of the incoming order applies.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    CANCEL_NEWEST,        // the incoming order is cancelled.
+    CANCEL_OLDEST,        // the resting order is cancelled and matching goes on.
+    CANCEL_BOTH,          // both orders are cancelled.
+    DECREMENT_AND_CANCEL, // both orders are decremented by the smaller size, the smaller one is cancelled.
+}
+
+pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 // ---------- ORDER THAT IS NOT A PART OF LIMIT ORDER BOOK YET ----------
 
 ///
@@ -26,10 +75,20 @@ pub struct RawOrder {
     pub seq_id: u128,
     pub order_id: String,
     pub quote: String,
-    pub price: f64,
-    pub size: u64,
+    pub price: Price,
+    pub size: Qty,
     pub side: Side,
     pub order_type: OrderType,
+    pub protection_price: Option<Price>, // worst price a MARKET order is allowed to trade at.
+    pub time_in_force: TimeInForce,
+    pub timestamp: u64, // time at which the sequencer sequenced the order.
+    pub command: Command,
+    pub post_only: Option<PostOnly>, // the order may only add liquidity to the book.
+    pub reduce_only: bool,           // the order may only reduce the position of its owner.
+    pub display_size: Option<Qty>,   // peak shown by an iceberg order, the rest is hidden.
+    pub stop_price: Option<Price>,   // last trade price that releases a stop order into matching.
+    pub owner_id: u64,               // account the order belongs to.
+    pub self_trade_prevention: Option<SelfTradePrevention>,
 }
 
 impl Default for RawOrder {
@@ -38,10 +97,20 @@ impl Default for RawOrder {
             seq_id: 0,
             order_id: "DEFAULT_ORDER".into(),
             quote: "DEFAULT".into(),
-            price: 0.0,
-            size: 0,
+            price: Price(0),
+            size: Qty(0),
             side: Side::BID,
             order_type: OrderType::LIMIT,
+            protection_price: None,
+            time_in_force: TimeInForce::GTC,
+            timestamp: 0,
+            command: Command::NEW,
+            post_only: None,
+            reduce_only: false,
+            display_size: None,
+            stop_price: None,
+            owner_id: 0,
+            self_trade_prevention: None,
         }
     }
 }
@@ -62,12 +131,12 @@ impl RawOrder {
         self
     }
 
-    pub fn with_price(&mut self, price: f64) -> &mut Self {
+    pub fn with_price(&mut self, price: Price) -> &mut Self {
         self.price = price;
         self
     }
 
-    pub fn with_size(&mut self, size: u64) -> &mut Self {
+    pub fn with_size(&mut self, size: Qty) -> &mut Self {
         self.size = size;
         self
     }
@@ -81,6 +150,59 @@ impl RawOrder {
         self.order_type = order_type;
         self
     }
+
+    pub fn with_protection_price(&mut self, protection_price: Option<Price>) -> &mut Self {
+        self.protection_price = protection_price;
+        self
+    }
+
+    pub fn with_time_in_force(&mut self, time_in_force: TimeInForce) -> &mut Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_timestamp(&mut self, timestamp: u64) -> &mut Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_command(&mut self, command: Command) -> &mut Self {
+        self.command = command;
+        self
+    }
+
+    pub fn with_post_only(&mut self, post_only: Option<PostOnly>) -> &mut Self {
+        self.post_only = post_only;
+        self
+    }
+
+    pub fn with_reduce_only(&mut self, reduce_only: bool) -> &mut Self {
+        self.reduce_only = reduce_only;
+        self
+    }
+
+    pub fn with_display_size(&mut self, display_size: Option<Qty>) -> &mut Self {
+        self.display_size = display_size;
+        self
+    }
+
+    pub fn with_stop_price(&mut self, stop_price: Option<Price>) -> &mut Self {
+        self.stop_price = stop_price;
+        self
+    }
+
+    pub fn with_owner_id(&mut self, owner_id: u64) -> &mut Self {
+        self.owner_id = owner_id;
+        self
+    }
+
+    pub fn with_self_trade_prevention(
+        &mut self,
+        self_trade_prevention: Option<SelfTradePrevention>,
+    ) -> &mut Self {
+        self.self_trade_prevention = self_trade_prevention;
+        self
+    }
 }
 
 // ---------- MESSAGE USED BY ORDER MANAGER AND SEQUECNER ----------
@@ -94,12 +216,83 @@ pub struct RawMessage {}
 
 // ---------- EVENTS ----------
 
+/// The reason why the matching engine refused a request.
+#[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// The order is not resting in the book, it is either unknown or already filled.
+    UNKNOWN_ORDER,
+    /// The requested size can't be used for the order.
+    INVALID_SIZE,
+    /// The price is not a multiple of the tick size of the instrument.
+    OFF_TICK,
+    /// The size is not a multiple of the lot size of the instrument.
+    OFF_LOT,
+    /// The price is outside the allowed price range of the instrument.
+    PRICE_OUT_OF_RANGE,
+    /// The size is outside the allowed order size range of the instrument.
+    SIZE_OUT_OF_RANGE,
+    /// The instrument is not open for trading.
+    HALTED,
+    /// An order with the same order id is already resting in the book.
+    DUPLICATE_ORDER_ID,
+    /// The order was sent for a quote the sequencer does not handle.
+    UNKNOWN_INSTRUMENT,
+    /// The request could not be decoded or does not fit in a sequenced message.
+    MALFORMED,
+    /// The matching engine can't take more orders right now, the request was not passed to it.
+    THROTTLED,
+    /// The order breaches a risk limit of the account.
+    RISK_LIMIT,
+    /// The order would have traded against an order of the same owner.
+    SELF_TRADE,
+    /// The post-only order would have taken liquidity on arrival.
+    WOULD_TAKE_LIQUIDITY,
+    /// The order asks for a feature the matching engine does not support yet, such as a
+    /// reduce-only order while the positions of the accounts are not tracked.
+    UNSUPPORTED,
+}
+
+/// A match between the incoming (aggressor) order and a resting (passive) order.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Trade {
+    pub trade_id: u64, // increases by one for every match of the book.
+    pub aggressor_order_id: String,
+    pub aggressor_seq_id: u128,
+    pub passive_order_id: String,
+    pub passive_seq_id: u128,
+    pub price: Price, // the price of the passive order.
+    pub qty: Qty,
+    pub aggressor_side: Side,
+    pub timestamp: u64, // engine clock, the timestamp of the last sequenced order.
+}
+
+/// The execution report of a single trade for one of the two matched orders.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Fill {
+    pub trade_id: u64,
+    pub price: Price,
+    pub qty: Qty,        // quantity traded by this trade.
+    pub cum_qty: Qty,    // quantity traded by the order so far.
+    pub leaves_qty: Qty, // quantity of the order still open for execution.
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Execution {
     INSERTED,
-    CANCELLED,
-    FILL,
-    PARTIAL(f64, u64),
+    CANCELLED(Qty), // remaining quantity of the cancelled order.
+    FILL(Fill),     // the last trade of the order, nothing is left open.
+    PARTIAL(Fill),
+    EXPIRED(Qty), // unfilled quantity that was not allowed to rest in the book.
+    REJECTED(RejectReason),
+    AMENDED(Price, Qty), // new price and remaining size of the amended order.
+    TRADE(Trade),
+    REPRICED(Price), // the post-only order slid to the given price to avoid crossing the book.
+    PENDING,         // the stop order waits in the trigger book.
+    TRIGGERED(Price), // the stop order was released into matching by the given last trade price.
+    SELF_TRADE_CANCELLED(Qty), // remaining quantity cancelled by self-trade prevention.
+    SELF_TRADE_DECREMENTED(Qty, Qty), // quantity taken off by self-trade prevention and the remaining quantity.
 }
 
 // ---------- EVENTS WITH SEQ-ID ----------
@@ -134,14 +327,23 @@ impl ExecuteMessage {
 pub struct OrderValue {
     pub quote: String,
     pub order_id: String,
-    pub price: f64,
-    pub size: u64,
+    pub price: Price,
+    pub size: Qty,
     pub side: Side,
     pub order_type: OrderType,
+    pub protection_price: Option<Price>,
+    pub time_in_force: TimeInForce,
+    pub command: Command,
+    pub post_only: Option<PostOnly>,
+    pub reduce_only: bool,
+    pub display_size: Option<Qty>,
+    pub stop_price: Option<Price>,
+    pub owner_id: u64,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
 }
 
 impl OrderValue {
-    pub fn into_raw(&self, seq: u128) -> RawOrder {
+    pub fn into_raw(&self, seq: u128, timestamp: u64) -> RawOrder {
         RawOrder::default()
             .with_seq_id(seq)
             .with_order_id(self.order_id.clone())
@@ -150,6 +352,16 @@ impl OrderValue {
             .with_size(self.size)
             .with_side(self.side)
             .with_order_type(self.order_type)
+            .with_protection_price(self.protection_price)
+            .with_time_in_force(self.time_in_force)
+            .with_timestamp(timestamp)
+            .with_command(self.command)
+            .with_post_only(self.post_only)
+            .with_reduce_only(self.reduce_only)
+            .with_display_size(self.display_size)
+            .with_stop_price(self.stop_price)
+            .with_owner_id(self.owner_id)
+            .with_self_trade_prevention(self.self_trade_prevention)
             .to_owned()
     }
 }
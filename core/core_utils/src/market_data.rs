@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{L2Snapshot, Level, Price, Qty, Side};
+
+// ---------- MARKET DATA FEED ----------
+
+/// An incremental update of the public view of the book.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketData {
+    /// A snapshot of the whole book follows as the given number of `ADD` messages,
+    /// consumers drop their book and rebuild it from them.
+    SNAPSHOT(u32),
+    /// A new price level.
+    ADD(Side, Level),
+    /// The aggregated volume or the order count of a price level changed.
+    UPDATE(Side, Level),
+    /// The price level has no orders left.
+    DELETE(Side, Price),
+    /// A trade, without the identity of the counterparties.
+    TRADE {
+        trade_id: u64,
+        price: Price,
+        qty: Qty,
+        aggressor_side: Side,
+        timestamp: u64,
+    },
+    /// The best bid or the best ask level changed.
+    TOP_OF_BOOK {
+        bid: Option<Level>,
+        ask: Option<Level>,
+    },
+}
+
+/// A market data update with the sequence number of the message within the feed, which
+/// increases by one for every message so consumers can detect gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketDataMessage {
+    pub msg_seq: u64,
+    pub seq_id: u128, // sequence id of the order that caused the update.
+    pub event: MarketData,
+}
+
+impl MarketDataMessage {
+    /// Largest encoded message of the feed, the slots of the market data queue are sized from
+    /// it. Every field has a fixed encoded size, the largest event is `TOP_OF_BOOK`.
+    /// ```rust
+    /// use core_utils::{Level, MarketData, MarketDataMessage, Price, Qty};
+    ///
+    /// let level = Some(Level { price: Price(i64::MAX), vol: Qty(u64::MAX), orders: u32::MAX });
+    /// let top = MarketDataMessage::new(u64::MAX, u128::MAX, MarketData::TOP_OF_BOOK { bid: level, ask: level });
+    /// assert!(top.as_bytes().len() <= MarketDataMessage::MAX_ENCODED_SIZE);
+    /// ```
+    pub const MAX_ENCODED_SIZE: usize = 128;
+
+    pub fn new(msg_seq: u64, seq_id: u128, event: MarketData) -> Self {
+        Self {
+            msg_seq,
+            seq_id,
+            event,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&self).unwrap()
+    }
+}
+
+/// Returned when a message of the feed was missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub expected: u64,
+    pub received: u64,
+}
+
+/// The level 2 book rebuilt by a consumer of the market data feed. The book is only in sync
+/// once a snapshot was received, after a gap it waits for the next snapshot.
+/// ```rust
+/// use core_utils::{L2Book, Level, MarketData, MarketDataMessage, Price, Qty, Side};
+///
+/// let level = Level { price: Price(10000), vol: Qty(10), orders: 1 };
+/// let mut book = L2Book::default();
+/// book.apply(&MarketDataMessage::new(1, 0, MarketData::SNAPSHOT(1))).unwrap();
+/// book.apply(&MarketDataMessage::new(2, 0, MarketData::ADD(Side::ASK, level))).unwrap();
+/// assert!(book.is_synced());
+/// assert_eq!(book.snapshot(10).asks, vec![level]);
+///
+/// // the message with sequence number 3 was missed.
+/// assert!(book.apply(&MarketDataMessage::new(4, 0, MarketData::DELETE(Side::ASK, Price(10000)))).is_err());
+/// assert!(!book.is_synced());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct L2Book {
+    pub quote: String,
+    pub asks: BTreeMap<Price, Level>,
+    pub bids: BTreeMap<Price, Level>,
+    pub msg_seq: u64, // sequence number of the last applied message.
+    synced: bool,
+}
+
+impl L2Book {
+    pub fn new(quote: &str) -> Self {
+        Self {
+            quote: quote.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Applies the next message of the feed to the book.
+    pub fn apply(&mut self, msg: &MarketDataMessage) -> Result<(), SequenceGap> {
+        let expected = self.msg_seq + 1;
+        self.msg_seq = msg.msg_seq;
+
+        if let MarketData::SNAPSHOT(_) = msg.event {
+            self.asks.clear();
+            self.bids.clear();
+            self.synced = true;
+            return Ok(());
+        }
+        if !self.synced {
+            return Ok(());
+        }
+        if msg.msg_seq != expected {
+            self.synced = false;
+            return Err(SequenceGap {
+                expected,
+                received: msg.msg_seq,
+            });
+        }
+
+        match msg.event {
+            MarketData::ADD(side, level) | MarketData::UPDATE(side, level) => {
+                self.levels(side).insert(level.price, level);
+            }
+            MarketData::DELETE(side, price) => {
+                self.levels(side).remove(&price);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn levels(&mut self, side: Side) -> &mut BTreeMap<Price, Level> {
+        match side {
+            Side::ASK => &mut self.asks,
+            Side::BID => &mut self.bids,
+        }
+    }
+
+    /// Returns the top `depth` levels of the book, in the same order as the snapshot of the
+    /// limit order book.
+    pub fn snapshot(&self, depth: usize) -> L2Snapshot {
+        L2Snapshot {
+            quote: self.quote.clone(),
+            asks: self.asks.values().take(depth).copied().collect(),
+            bids: self.bids.values().rev().take(depth).copied().collect(),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Price, Qty};
+
+// ---------- BOOK SNAPSHOTS ----------
+
+/// A price level of the aggregated (level 2) view of the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Level {
+    pub price: Price,
+    pub vol: Qty,    // displayed volume of the level.
+    pub orders: u32, // number of orders resting at the level.
+}
+
+/// The top price levels of both sides of the book, asks are ordered from the lowest price
+/// and bids from the highest price.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L2Snapshot {
+    pub quote: String,
+    pub asks: Vec<Level>,
+    pub bids: Vec<Level>,
+}
+
+/// A resting order of the order by order (level 3) view of the book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L3Order {
+    pub seq_id: u128,
+    pub order_id: String,
+    pub price: Price,
+    pub size: Qty, // displayed size of the order.
+}
+
+/// Every resting order of the book, the levels are ordered like in [`L2Snapshot`] and the
+/// orders within a level are in queue order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L3Snapshot {
+    pub quote: String,
+    pub asks: Vec<L3Order>,
+    pub bids: Vec<L3Order>,
+}
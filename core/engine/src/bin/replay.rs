@@ -0,0 +1,42 @@
+use std::io::{BufWriter, Write};
+
+use anyhow::anyhow;
+use core_utils::{Instrument, RawOrder, NANOS_PER_DAY};
+use matching_engine::matching::EngineState;
+use memmap::JournalReader;
+
+/// Replays the journal of the sequencer on a fresh book and prints every execution, one per
+/// line, so the output can be diffed against the executions the engine emitted.
+///
+/// usage: replay <QUOTE> <JOURNAL> [INSTRUMENTS]
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.len() < 3 || args.len() > 4 {
+        return Err(anyhow!("usage: replay <QUOTE> <JOURNAL> [INSTRUMENTS]"));
+    }
+    let (quote, journal) = (&args[1], &args[2]);
+
+    let instrument = match args.get(3) {
+        Some(path) => Instrument::load(path)?
+            .into_iter()
+            .find(|instrument| instrument.symbol == *quote)
+            .ok_or_else(|| anyhow!("no instrument definition for {}", quote))?,
+        None => Instrument::new(quote),
+    };
+    let mut state = EngineState::new(quote.clone(), instrument, NANOS_PER_DAY);
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    for record in JournalReader::open(journal, 0)? {
+        let (_, payload) = record?;
+        // an empty record is a request the sequencer rejected, it never reached the engine.
+        if payload.is_empty() {
+            continue;
+        }
+        let order: RawOrder = bincode::deserialize(&payload)?;
+        for msg in state.process(order) {
+            writeln!(out, "{:?}", msg)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
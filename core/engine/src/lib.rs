@@ -1,121 +1,250 @@
+use std::{path::PathBuf, thread::JoinHandle};
+
 use anyhow::{anyhow, Ok};
-use core_utils::{ExecuteMessage, Execution, RawOrder, Side};
+use core_utils::{
+    ExecuteMessage, Execution, Instrument, MarketData, MarketDataMessage, RawOrder, TimeInForce,
+    NANOS_PER_DAY,
+};
 use crossbeam::channel::Receiver;
-use lob::LimitOrderBook;
-use memmap::MmapQueue;
+use market_data::MarketDataPublisher;
+use matching::EngineState;
+use memmap::{MmapQueue, QueueMode, SeqCursor};
+use snapshot::EngineSnapshot;
+
+pub mod market_data;
+pub mod matching;
+pub mod snapshot;
 
 pub fn tmp_path(name: &str) -> std::path::PathBuf {
     std::env::temp_dir().join(format!("mmap_queue_{}.dat", name))
 }
 
+/// Returns the timestamp at which an order with the given time in force expires,
+/// `session_end` is the time of the day in nanoseconds at which the trading session ends.
+pub fn expiry(time_in_force: TimeInForce, timestamp: u64, session_end: u64) -> Option<u64> {
+    match time_in_force {
+        TimeInForce::DAY => {
+            let end = timestamp - timestamp % NANOS_PER_DAY + session_end;
+            if end > timestamp {
+                Some(end)
+            } else {
+                Some(end + NANOS_PER_DAY)
+            }
+        }
+        TimeInForce::GTD(at) => Some(at),
+        _ => None,
+    }
+}
+
+/// Publishes an execution on the outbound queue, when the queue is full it waits for the
+/// consumer to make room instead of dropping the event.
+fn emit(queue: &mut MmapQueue, msg: &ExecuteMessage) -> anyhow::Result<()> {
+    while queue.is_full() {
+        std::thread::yield_now();
+    }
+    queue.enqueue(&msg.as_bytes())
+}
+
 pub struct MatchingEngine {
     pub quote: String,
     pub inbound_queue: *mut MmapQueue,
     pub outbound_queue: *mut MmapQueue,
+    pub session_end: u64, // nanoseconds after midnight (UTC) at which DAY orders expire.
+    pub instrument: Instrument, // reference data every incoming order is validated against.
+    pub market_data_queue: *mut MmapQueue, // public book deltas, trades and top of book of the quote.
+    pub snapshot_interval: u64, // sequenced orders between two book snapshots on the market data feed.
+    pub book_snapshot_path: Option<PathBuf>, // file the state of the engine is saved to, `None` keeps it in memory only.
+    pub book_snapshot_interval: u64, // sequenced orders between two snapshots of the engine state.
 }
 
-
-
 impl MatchingEngine {
     pub fn new(quote: String) -> anyhow::Result<Self> {
         let inbound = MmapQueue::open(tmp_path(&format!("{}-inbound", quote)))?;
         let outbound = MmapQueue::open(tmp_path(&format!("{}-outbound", quote)))?;
+        // the engine is the only writer of the market data feed, so it owns the queue. Every
+        // consumer of the feed reads it through its own `Subscriber`. The slots are sized from
+        // the encoded messages, not from their size in memory. The queue outlives a restart of
+        // the engine, the subscribers keep their place and resync from the snapshot published
+        // at startup.
+        let market_data = MmapQueue::open_or_create_with_mode(
+            tmp_path(&format!("{}-marketdata", quote)),
+            1024,
+            MarketDataMessage::MAX_ENCODED_SIZE,
+            QueueMode::BROADCAST,
+        )?;
 
-        
         Ok(Self {
             quote: quote.clone(),
             inbound_queue: Box::into_raw(Box::new(inbound)),
             outbound_queue: Box::into_raw(Box::new(outbound)),
+            session_end: NANOS_PER_DAY,
+            instrument: Instrument::new(&quote),
+            market_data_queue: Box::into_raw(Box::new(market_data)),
+            snapshot_interval: 1024,
+            book_snapshot_path: None,
+            book_snapshot_interval: 4096,
         })
     }
 
-    pub fn get_inbound(&self)->anyhow::Result<&mut MmapQueue>{
-        if let Some(queue)=unsafe{self.inbound_queue.as_mut()}{
-            return Ok(queue)
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_inbound(&self) -> anyhow::Result<&mut MmapQueue> {
+        if let Some(queue) = unsafe { self.inbound_queue.as_mut() } {
+            return Ok(queue);
         }
 
         Err(anyhow!("Inbound queue is null pointer"))
     }
 
-    pub fn get_outbound(&self)->anyhow::Result<&mut MmapQueue>{
-        if let Some(queue)=unsafe{self.outbound_queue.as_mut()}{
-            return Ok(queue)
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_outbound(&self) -> anyhow::Result<&mut MmapQueue> {
+        if let Some(queue) = unsafe { self.outbound_queue.as_mut() } {
+            return Ok(queue);
         }
 
         Err(anyhow!("Inbound queue is null pointer"))
     }
 
-    pub fn run(&self, rx: Receiver<RawOrder>) -> anyhow::Result<()> {
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_market_data(&self) -> anyhow::Result<&mut MmapQueue> {
+        if let Some(queue) = unsafe { self.market_data_queue.as_mut() } {
+            return Ok(queue);
+        }
+
+        Err(anyhow!("Market data queue is null pointer"))
+    }
+
+    /// Reads the last snapshot of the engine state, `None` when there is nothing to restore.
+    pub fn load_snapshot(&self) -> anyhow::Result<Option<EngineSnapshot>> {
+        let snapshot = match &self.book_snapshot_path {
+            Some(path) => EngineSnapshot::load(path)?,
+            None => return Ok(None),
+        };
+        if let Some(snapshot) = &snapshot {
+            if snapshot.quote != self.quote {
+                return Err(anyhow!(
+                    "snapshot of {} can't be restored into {}",
+                    snapshot.quote,
+                    self.quote
+                ));
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// The cursor of the last processed sequence id, kept next to the snapshot of the engine
+    /// state so both survive together. The sequencer reads it from the same directory as its
+    /// journal, `None` when the state is kept in memory only.
+    pub fn progress_path(&self) -> Option<PathBuf> {
+        self.book_snapshot_path
+            .as_ref()
+            .map(|path| path.with_file_name(format!("{}.progress", self.quote)))
+    }
+
+    /// Spawns the matching thread, the returned handle yields the error that stopped
+    /// the thread if the outbound queue can't be written anymore. The thread starts from the
+    /// last snapshot of the engine state when there is one, the orders sequenced after it
+    /// must be sent again. The executions of the orders it had already reported before a
+    /// restart are not reported again.
+    pub fn run(&self, rx: Receiver<RawOrder>) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
         if self.outbound_queue.is_null() {
             return Err(anyhow!("Outbound queue is a null pointer"));
         }
         let outbound_queue = unsafe { self.outbound_queue.as_mut() }.unwrap();
+        let market_data_queue = unsafe { self.market_data_queue.as_mut() }.unwrap();
 
         let quote = self.quote.clone();
-        std::thread::spawn(move || {
-            let mut lob = LimitOrderBook::from(quote);
-            for mut seq_order in rx {
-                let mut outorder_execution =
-                    ExecuteMessage::new(seq_order.seq_id, Execution::INSERTED);
-                let side = seq_order.side;
-                let other_side = match seq_order.side {
-                    Side::BID => lob.best_ask.clone(),
-                    Side::ASK => lob.best_bid.clone(),
-                };
-
-                match other_side {
-                    Some(order) => {
-                        let is_match = match order.borrow().side {
-                            Side::ASK => seq_order.price >= order.borrow().price,
-                            Side::BID => order.borrow().price >= seq_order.price,
-                        };
-                        // if match found
-                        if is_match {
-                            // Evalute the quantity to trade
-                            let quantity_to_trade =
-                                std::cmp::min(order.borrow().size, seq_order.size);
-                            let mut inorder_execution = ExecuteMessage::new(
-                                order.borrow().seq_id,
-                                Execution::PARTIAL(order.borrow().price, quantity_to_trade),
-                            );
-
-                            // trade orders
-                            seq_order.size -= quantity_to_trade;
-
-                            order.borrow_mut().size -= quantity_to_trade;
-
-                            if order.borrow().size == 0 {
-                                lob.remove(order.borrow().order_id.clone());
-                                lob.update_best(order.borrow().side);
-                                inorder_execution.set_execution(Execution::FILL);
-                            }
-
-                            // emit inorder execution
-                            let _ = outbound_queue.enqueue(&inorder_execution.as_bytes());
-                        }
+        let session_end = self.session_end;
+        let instrument = self.instrument.clone();
+        let snapshot_interval = self.snapshot_interval.max(1);
+        let book_snapshot_path = self.book_snapshot_path.clone();
+        let book_snapshot_interval = self.book_snapshot_interval.max(1);
+        let restored = self.load_snapshot()?;
+        // the last processed sequence id, the sequencer resends every order after it on restart.
+        let mut progress = self.progress_path().map(SeqCursor::open).transpose()?;
+        let handle = std::thread::spawn(move || {
+            let mut state = match &restored {
+                Some(snapshot) => snapshot.restore(instrument, session_end),
+                None => EngineState::new(quote, instrument, session_end),
+            };
+            let mut last_seq_id: Option<u128> = restored.as_ref().and_then(|s| s.last_seq_id);
+            // the executions of the orders up to the cursor were reported before a restart, the
+            // orders between the snapshot and the cursor only rebuild the state.
+            let mut replaying = progress
+                .as_ref()
+                .and_then(|progress| progress.load())
+                .filter(|reported| last_seq_id.is_none_or(|last| *reported > last));
+            // consumers of the market data feed start from a snapshot of the book, taken once
+            // the state caught up with what was reported.
+            let mut market_data = MarketDataPublisher::new(market_data_queue)?;
+            if replaying.is_none() {
+                market_data.publish_snapshot(&mut state.lob, last_seq_id.unwrap_or_default())?;
+            }
+            let mut sequenced: u64 = 0;
+            // the state is saved once every order sequenced before the next one was processed.
+            let save = |state: &EngineState, last_seq_id| match &book_snapshot_path {
+                Some(path) => EngineSnapshot::capture(state, last_seq_id).write(path),
+                None => Ok(()),
+            };
+            // the loop ends once every sender is gone.
+            while let Result::Ok(seq_order) = rx.recv() {
+                // an order is sent again when the sequencer or the engine recovers
+                // from a crash, it was already processed if it is not after the last one.
+                if last_seq_id.is_some_and(|last| seq_order.seq_id <= last) {
+                    continue;
+                }
+                let seq_id = seq_order.seq_id;
+                let previous = last_seq_id.replace(seq_id);
 
-                        if seq_order.size != 0 {
-                            lob.insert(RawOrder::from(seq_order));
-                            lob.update_best(side);
+                if let Some(reported) = replaying.filter(|reported| seq_id <= *reported) {
+                    state.process(seq_order);
+                    if seq_id == reported {
+                        replaying = None;
+                        market_data.publish_snapshot(&mut state.lob, seq_id)?;
+                    }
+                } else {
+                    // the last reported order was never sent again, the feed resyncs before
+                    // the changes of this order.
+                    if replaying.take().is_some() {
+                        market_data
+                            .publish_snapshot(&mut state.lob, previous.unwrap_or_default())?;
+                    }
+                    for msg in state.process(seq_order) {
+                        emit(outbound_queue, &msg)?;
+                        // the public feed only shows the trade, not the orders behind it.
+                        if let Execution::TRADE(trade) = &msg.execution {
+                            market_data.publish(
+                                msg.seq_id,
+                                MarketData::TRADE {
+                                    trade_id: trade.trade_id,
+                                    price: trade.price,
+                                    qty: trade.qty,
+                                    aggressor_side: trade.aggressor_side,
+                                    timestamp: trade.timestamp,
+                                },
+                            )?;
                         }
                     }
-                    None => {
-                        // Insert order directly
-                        lob.insert(RawOrder::from(seq_order));
-                        // update the best side order that
-                        // belongs to this order's side.
-                        lob.update_best(side);
-
-                        outorder_execution.set_execution(Execution::INSERTED);
+                    // the book changes of the order are published before the next one.
+                    market_data.publish_changes(&mut state.lob, seq_id)?;
+                    if let Some(progress) = progress.as_mut() {
+                        progress.store(seq_id);
                     }
                 }
 
-                // emit execution event.
-                let _ = outbound_queue.enqueue(&outorder_execution.as_bytes());
+                sequenced += 1;
+                if replaying.is_none() && sequenced.is_multiple_of(snapshot_interval) {
+                    market_data.publish_snapshot(&mut state.lob, seq_id)?;
+                }
+                if sequenced.is_multiple_of(book_snapshot_interval) {
+                    save(&state, last_seq_id)?;
+                }
             }
+            // the engine stops with a snapshot of its last state.
+            save(&state, last_seq_id)?;
+            Ok(())
         });
 
-        Ok(())
+        Ok(handle)
     }
 }
 
@@ -123,5 +252,6 @@ impl Drop for MatchingEngine {
     fn drop(&mut self) {
         let _ = unsafe { Box::from_raw(self.inbound_queue) };
         let _ = unsafe { Box::from_raw(self.outbound_queue) };
+        let _ = unsafe { Box::from_raw(self.market_data_queue) };
     }
 }
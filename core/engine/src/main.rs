@@ -1,25 +1,43 @@
-use core_utils::RawOrder;
+use anyhow::anyhow;
+use core_utils::{Instrument, RawOrder};
 
-use std::thread::sleep;
-use std::time::Duration;
-
-use matching_engine::MatchingEngine;
+use matching_engine::{snapshot::sequenced_after, MatchingEngine};
+use memmap::AsyncReceiver;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let matching_engine = MatchingEngine::new("BTCETH".into())?;
+    let mut matching_engine = MatchingEngine::new("BTCETH".into())?;
+    // reference data of the instrument is loaded once at startup.
+    matching_engine.instrument = Instrument::load("instruments.json")?
+        .into_iter()
+        .find(|instrument| instrument.symbol == matching_engine.quote)
+        .ok_or_else(|| anyhow!("no instrument definition for {}", matching_engine.quote))?;
+    matching_engine.book_snapshot_path =
+        Some(format!("{}.snapshot.dat", matching_engine.quote).into());
     let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
 
-    let inbound_queue = matching_engine.get_inbound()?;
+    // the task sleeps until the sequencer publishes the next order.
+    let mut inbound_queue = AsyncReceiver::new(matching_engine.get_inbound()?)?;
 
+    // the engine restarts from its last snapshot, then the orders sequenced after it are
+    // replayed from the sequencer journal before the live orders. The engine skips the orders
+    // it already processed, so an order both replayed and still queued is matched once, and
+    // the orders it reported before the restart only rebuild its state.
+    let last_seq_id = matching_engine
+        .load_snapshot()?
+        .and_then(|snapshot| snapshot.last_seq_id);
     matching_engine.run(rx.clone())?;
-
-    loop {
-        while let Ok(Some(s)) = inbound_queue.dequeue() {
-            let msg: RawOrder = bincode::deserialize(&s).unwrap();
-            let _ = tx.send(msg);
+    let journal = format!("{}.journal", matching_engine.quote);
+    if std::path::Path::new(&journal).exists() {
+        for order in sequenced_after(&journal, last_seq_id)? {
+            tx.send(order)?;
         }
+    }
 
-        sleep(Duration::from_millis(5));
+    loop {
+        let s = inbound_queue.recv().await?;
+        // the sequencer only publishes encoded raw orders, anything else is a broken queue.
+        let msg: RawOrder = bincode::deserialize(&s)?;
+        tx.send(msg)?;
     }
 }
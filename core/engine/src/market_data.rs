@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use core_utils::{Level, MarketData, MarketDataMessage, Price, Side};
+use lob::LimitOrderBook;
+use memmap::MmapQueue;
+
+/// Publishes the public view of the book on the broadcast market data queue. Every message
+/// takes the next message sequence number, the queue never stalls the matching, a subscriber
+/// which falls behind is lapped, sees a gap and waits for the next snapshot.
+pub struct MarketDataPublisher<'a> {
+    queue: &'a mut MmapQueue,
+    pub msg_seq: u64, // sequence number of the last published message.
+    levels: HashMap<(Side, Price), Level>, // every level as it was last published.
+    top: (Option<Level>, Option<Level>), // best bid and best ask as last published.
+}
+
+impl<'a> MarketDataPublisher<'a> {
+    /// The message sequence numbers carry on after the last message left in the queue by a
+    /// previous run, so a restart never looks like the feed went back in time.
+    pub fn new(queue: &'a mut MmapQueue) -> anyhow::Result<Self> {
+        let msg_seq = match queue.peek_all()?.last() {
+            Some(bytes) => bincode::deserialize::<MarketDataMessage>(bytes)?.msg_seq,
+            None => 0,
+        };
+        Ok(Self {
+            queue,
+            msg_seq,
+            levels: HashMap::new(),
+            top: (None, None),
+        })
+    }
+
+    pub fn publish(&mut self, seq_id: u128, event: MarketData) -> anyhow::Result<()> {
+        let msg = MarketDataMessage::new(self.msg_seq + 1, seq_id, event);
+        let bytes = msg.as_bytes();
+        // a larger message would not fit the slots of the feed, it never takes a sequence number.
+        if bytes.len() > MarketDataMessage::MAX_ENCODED_SIZE {
+            bail!(
+                "market data message of {} bytes is larger than {} bytes",
+                bytes.len(),
+                MarketDataMessage::MAX_ENCODED_SIZE
+            );
+        }
+        self.queue.enqueue(&bytes)?;
+        self.msg_seq += 1;
+        Ok(())
+    }
+
+    /// Publishes the levels changed by the order with the given sequence id, followed by the
+    /// top of the book if it changed.
+    pub fn publish_changes(
+        &mut self,
+        lob: &mut LimitOrderBook,
+        seq_id: u128,
+    ) -> anyhow::Result<()> {
+        for (side, price) in lob.take_changed_levels() {
+            let level = lob.level(side, price);
+            let event = match (self.levels.get(&(side, price)), level) {
+                (None, Some(level)) => MarketData::ADD(side, level),
+                (Some(published), Some(level)) if *published != level => {
+                    MarketData::UPDATE(side, level)
+                }
+                (Some(_), None) => MarketData::DELETE(side, price),
+                _ => continue,
+            };
+            match level {
+                Some(level) => self.levels.insert((side, price), level),
+                None => self.levels.remove(&(side, price)),
+            };
+            self.publish(seq_id, event)?;
+        }
+
+        let top = top_of_book(lob);
+        if top != self.top {
+            self.publish_top(top, seq_id)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes every level of the book, consumers rebuild their book from it.
+    pub fn publish_snapshot(
+        &mut self,
+        lob: &mut LimitOrderBook,
+        seq_id: u128,
+    ) -> anyhow::Result<()> {
+        // the snapshot already carries every pending change.
+        lob.take_changed_levels();
+        let snapshot = lob.l2_snapshot(usize::MAX);
+
+        let levels: Vec<(Side, Level)> = snapshot
+            .asks
+            .into_iter()
+            .map(|level| (Side::ASK, level))
+            .chain(snapshot.bids.into_iter().map(|level| (Side::BID, level)))
+            .collect();
+        self.levels = levels
+            .iter()
+            .map(|(side, level)| ((*side, level.price), *level))
+            .collect();
+
+        self.publish(seq_id, MarketData::SNAPSHOT(levels.len() as u32))?;
+        for (side, level) in levels {
+            self.publish(seq_id, MarketData::ADD(side, level))?;
+        }
+        self.publish_top(top_of_book(lob), seq_id)
+    }
+
+    fn publish_top(
+        &mut self,
+        top: (Option<Level>, Option<Level>),
+        seq_id: u128,
+    ) -> anyhow::Result<()> {
+        self.top = top;
+        let (bid, ask) = top;
+        self.publish(seq_id, MarketData::TOP_OF_BOOK { bid, ask })
+    }
+}
+
+/// Returns the best bid and the best ask level of the book.
+fn top_of_book(lob: &LimitOrderBook) -> (Option<Level>, Option<Level>) {
+    let bid = lob
+        .best_bid
+        .as_ref()
+        .and_then(|order| lob.level(Side::BID, order.borrow().price));
+    let ask = lob
+        .best_ask
+        .as_ref()
+        .and_then(|order| lob.level(Side::ASK, order.borrow().price));
+    (bid, ask)
+}
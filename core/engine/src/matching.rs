@@ -0,0 +1,566 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use core_utils::{
+    Command, ExecuteMessage, Execution, Fill, Instrument, OrderType, PostOnly, Price, Qty,
+    RawOrder, RejectReason, SelfTradePrevention, Side, TimeInForce, Trade,
+};
+use lob::{
+    stop::{is_triggered, StopBook},
+    LimitOrderBook,
+};
+
+use crate::expiry;
+
+/// Reports the fill as the last execution of the order when nothing is left open.
+fn fill_report(fill: Fill) -> Execution {
+    if fill.leaves_qty.is_zero() {
+        Execution::FILL(fill)
+    } else {
+        Execution::PARTIAL(fill)
+    }
+}
+
+/// Returns the price one tick away from the best opposite order when an order of the given
+/// side and price would cross the book, `None` when it can rest at its own price.
+fn slide_price(lob: &LimitOrderBook, side: Side, price: Price, tick_size: Price) -> Option<Price> {
+    match side {
+        Side::BID => lob
+            .best_ask
+            .as_ref()
+            .map(|best| best.borrow().price)
+            .filter(|best| price >= *best)
+            .map(|best| Price(best.0 - tick_size.0)),
+        Side::ASK => lob
+            .best_bid
+            .as_ref()
+            .map(|best| best.borrow().price)
+            .filter(|best| *best >= price)
+            .map(|best| Price(best.0 + tick_size.0)),
+    }
+}
+
+/// Reports the quantity taken off an order by self-trade prevention, the order is cancelled
+/// when nothing is left of it.
+fn self_trade_execution(decrement: Qty, leaves: Qty) -> Execution {
+    if leaves.is_zero() {
+        Execution::SELF_TRADE_CANCELLED(decrement)
+    } else {
+        Execution::SELF_TRADE_DECREMENTED(decrement, leaves)
+    }
+}
+
+/// Returns whether the resting orders of the given side can fill a fill or kill order
+/// completely. Self-trade prevention never fills the order against its own owner: the resting
+/// orders of the owner are left out when they would be cancelled, and the order can't be
+/// filled when it would be decremented by one of them before it is filled.
+fn fok_fillable(lob: &LimitOrderBook, side: Side, price: Option<Price>, order: &RawOrder) -> bool {
+    let mode = match order.self_trade_prevention {
+        Some(mode) => mode,
+        None => return lob.liquidity(side, price, order.size) >= order.size,
+    };
+
+    let mut total = Qty::ZERO;
+    for limit in lob.levels(side) {
+        let limit = limit.borrow();
+        let tradable = match (side, price) {
+            (_, None) => true,
+            (Side::ASK, Some(price)) => limit.price <= price,
+            (Side::BID, Some(price)) => limit.price >= price,
+        };
+        if !tradable || total >= order.size {
+            break;
+        }
+
+        // the displayed peaks are matched in queue order, the hidden reserves only after them.
+        let mut reserve = Qty::ZERO;
+        for resting in limit.orders() {
+            let resting = resting.borrow();
+            if resting.owner_id == order.owner_id {
+                if mode != SelfTradePrevention::CANCEL_OLDEST && total < order.size {
+                    return false;
+                }
+                continue;
+            }
+            total += resting.visible;
+            reserve += resting.size - resting.visible;
+        }
+        total += reserve;
+    }
+    total >= order.size
+}
+
+/// The whole matching state of a quote. Sequenced orders are applied one by one with
+/// `process`, which only depends on this state and on the order, so replaying the same
+/// orders on a fresh state always yields the same executions.
+pub struct EngineState {
+    pub lob: LimitOrderBook,
+    pub stops: StopBook,
+    pub expiries: BTreeMap<(u64, u128), String>, // resting DAY and GTD orders keyed by their expiry time and sequence id.
+    pub trade_id: u64,                           // id of the last trade.
+    pub last_price: Option<Price>, // price of the last trade, the trigger of the stop orders.
+    pub clock: u64,                // the engine clock, the timestamp of the last sequenced order.
+    pub instrument: Instrument,    // reference data every incoming order is validated against.
+    pub session_end: u64,          // nanoseconds after midnight (UTC) at which DAY orders expire.
+}
+
+impl EngineState {
+    pub fn new(quote: String, instrument: Instrument, session_end: u64) -> Self {
+        Self {
+            lob: LimitOrderBook::from(quote),
+            stops: StopBook::new(),
+            expiries: BTreeMap::new(),
+            trade_id: 0,
+            last_price: None,
+            clock: 0,
+            instrument,
+            session_end,
+        }
+    }
+
+    /// Matches a sequenced order and returns its executions, followed by the executions of
+    /// the stop orders its trades triggered.
+    /// ```rust
+    /// use core_utils::{Instrument, Price, Qty, RawOrder, Side, NANOS_PER_DAY};
+    /// use matching_engine::matching::EngineState;
+    ///
+    /// let mut state = EngineState::new("BTCINR".into(), Instrument::new("BTCINR"), NANOS_PER_DAY);
+    /// let ask = RawOrder::default().with_seq_id(1).with_order_id("ASK".into()).with_price(Price(100)).with_size(Qty(10)).with_side(Side::ASK).to_owned();
+    /// let bid = RawOrder::default().with_seq_id(2).with_order_id("BID".into()).with_price(Price(100)).with_size(Qty(4)).with_side(Side::BID).to_owned();
+    ///
+    /// assert_eq!(state.process(ask).len(), 1);
+    /// // the trade, then the fills of the resting and of the incoming order.
+    /// assert_eq!(state.process(bid).len(), 3);
+    /// assert_eq!(state.last_price, Some(Price(100)));
+    /// ```
+    pub fn process(&mut self, order: RawOrder) -> Vec<ExecuteMessage> {
+        let mut events = Vec::new();
+        let mut released = VecDeque::from([order]);
+        while let Some(order) = released.pop_front() {
+            self.apply(order, &mut events);
+
+            // a match may have triggered stop orders, which are released in sequence order.
+            // Released orders can trade and trigger other stops in turn.
+            if let Some(last_price) = self.last_price {
+                for mut stop in self.stops.triggered(last_price) {
+                    stop.stop_price = None;
+                    events.push(ExecuteMessage::new(
+                        stop.seq_id,
+                        Execution::TRIGGERED(last_price),
+                    ));
+                    released.push_back(stop);
+                }
+            }
+        }
+        events
+    }
+
+    /// Matches a single order, its executions are appended to `events`.
+    fn apply(&mut self, mut seq_order: RawOrder, events: &mut Vec<ExecuteMessage>) {
+        // the engine clock only moves forward.
+        self.clock = self.clock.max(seq_order.timestamp);
+
+        // quantity the incoming order has traded before it reached the matching loop.
+        let mut cum_qty = Qty::ZERO;
+
+        // the sequencer clock has moved forward, so expire every resting
+        // order that outlived its time in force before matching.
+        while let Some(entry) = self.expiries.first_entry() {
+            if entry.key().0 > self.clock {
+                break;
+            }
+            let ((_, seq_id), order_id) = entry.remove_entry();
+            let resting = self
+                .lob
+                .ord_map
+                .get(&order_id)
+                .is_some_and(|order| order.borrow().seq_id == seq_id);
+            let pending = self
+                .stops
+                .get(&order_id)
+                .is_some_and(|order| order.seq_id == seq_id);
+            let size = if resting {
+                self.lob
+                    .remove(order_id)
+                    .map_or(Qty::ZERO, |order| order.size)
+            } else if pending {
+                self.stops
+                    .remove(&order_id)
+                    .map_or(Qty::ZERO, |order| order.size)
+            } else {
+                continue; // already filled or replaced.
+            };
+            let expired = ExecuteMessage::new(seq_id, Execution::EXPIRED(size));
+            events.push(expired);
+        }
+
+        // orders that don't fit the instrument never reach the book.
+        if let Err(reason) = self.instrument.validate(&seq_order) {
+            let rejected = ExecuteMessage::new(seq_order.seq_id, Execution::REJECTED(reason));
+            events.push(rejected);
+            return;
+        }
+
+        // a cancel request removes the resting order and reports what was left of it.
+        if seq_order.command == Command::CANCEL {
+            let size = match self.lob.remove(seq_order.order_id.clone()) {
+                Some(order) => Some(order.size),
+                None => self
+                    .stops
+                    .remove(&seq_order.order_id)
+                    .map(|order| order.size),
+            };
+            let execution = match size {
+                Some(size) => Execution::CANCELLED(size),
+                None => Execution::REJECTED(RejectReason::UNKNOWN_ORDER),
+            };
+            let cancelled = ExecuteMessage::new(seq_order.seq_id, execution);
+            events.push(cancelled);
+            return;
+        }
+
+        // an amend request changes the resting order in place, unless the new price
+        // crosses the book, then the order is taken out and matched again.
+        if seq_order.command == Command::AMEND {
+            let order = match self.lob.ord_map.get(&seq_order.order_id).cloned() {
+                Some(order) => order,
+                None => {
+                    let rejected = ExecuteMessage::new(
+                        seq_order.seq_id,
+                        Execution::REJECTED(RejectReason::UNKNOWN_ORDER),
+                    );
+                    events.push(rejected);
+                    return;
+                }
+            };
+
+            let (side, post_only) = (order.borrow().side, order.borrow().post_only);
+            let slid = slide_price(&self.lob, side, seq_order.price, self.instrument.tick_size);
+
+            // a post-only order is never amended into a price that takes liquidity.
+            if let (Some(mode), Some(price)) = (post_only, slid) {
+                let reason = match mode {
+                    PostOnly::REJECT => Some(RejectReason::WOULD_TAKE_LIQUIDITY),
+                    PostOnly::SLIDE if !self.instrument.price_in_range(price) => {
+                        Some(RejectReason::PRICE_OUT_OF_RANGE)
+                    }
+                    PostOnly::SLIDE => None,
+                };
+                if let Some(reason) = reason {
+                    let rejected =
+                        ExecuteMessage::new(seq_order.seq_id, Execution::REJECTED(reason));
+                    events.push(rejected);
+                    return;
+                }
+                seq_order.price = price;
+            }
+
+            let amended = ExecuteMessage::new(
+                seq_order.seq_id,
+                Execution::AMENDED(seq_order.price, seq_order.size),
+            );
+            events.push(amended);
+
+            let crosses = slid.is_some() && post_only.is_none();
+            if !crosses {
+                self.lob
+                    .amend(&seq_order.order_id, seq_order.price, seq_order.size);
+                return;
+            }
+
+            // the replaced order keeps its sequence id, its executed quantity and its
+            // flags, so its executions can be tracked and it still expires on time.
+            cum_qty = order.borrow().filled;
+            self.lob.remove(seq_order.order_id.clone());
+            seq_order = {
+                let order = order.borrow();
+                RawOrder::default()
+                    .with_seq_id(order.seq_id)
+                    .with_order_id(seq_order.order_id)
+                    .with_quote(seq_order.quote)
+                    .with_price(seq_order.price)
+                    .with_size(seq_order.size)
+                    .with_side(side)
+                    .with_order_type(order.order_type)
+                    .with_time_in_force(order.time_in_force)
+                    .with_post_only(order.post_only)
+                    .with_display_size(order.display_size)
+                    .with_owner_id(order.owner_id)
+                    .with_self_trade_prevention(order.self_trade_prevention)
+                    .with_timestamp(seq_order.timestamp)
+                    .to_owned()
+            };
+        }
+
+        // a reduce-only order may only shrink the position of its owner, the engine does not
+        // track positions yet so it can't tell whether the order would grow one.
+        if seq_order.command == Command::NEW && seq_order.reduce_only {
+            let rejected = ExecuteMessage::new(
+                seq_order.seq_id,
+                Execution::REJECTED(RejectReason::UNSUPPORTED),
+            );
+            events.push(rejected);
+            return;
+        }
+
+        // order ids identify the resting orders, so they must be unique in the book.
+        if seq_order.command == Command::NEW
+            && (self.lob.ord_map.contains_key(&seq_order.order_id)
+                || self.stops.contains(&seq_order.order_id))
+        {
+            let rejected = ExecuteMessage::new(
+                seq_order.seq_id,
+                Execution::REJECTED(RejectReason::DUPLICATE_ORDER_ID),
+            );
+            events.push(rejected);
+            return;
+        }
+
+        let side = seq_order.side;
+        let other_side = match side {
+            Side::BID => Side::ASK,
+            Side::ASK => Side::BID,
+        };
+
+        // a stop order waits in the trigger book until the last trade price reaches
+        // its stop price, if it already did the order is released right away.
+        if let Some(stop_price) = seq_order.stop_price {
+            match self
+                .last_price
+                .filter(|last| is_triggered(side, stop_price, *last))
+            {
+                Some(last_price) => {
+                    seq_order.stop_price = None;
+                    let triggered =
+                        ExecuteMessage::new(seq_order.seq_id, Execution::TRIGGERED(last_price));
+                    events.push(triggered);
+                }
+                None => {
+                    let expires_at = expiry(
+                        seq_order.time_in_force,
+                        seq_order.timestamp,
+                        self.session_end,
+                    );
+                    if let Some(at) = expires_at {
+                        self.expiries
+                            .insert((at, seq_order.seq_id), seq_order.order_id.clone());
+                    }
+                    let pending = ExecuteMessage::new(seq_order.seq_id, Execution::PENDING);
+                    self.stops.insert(seq_order);
+                    events.push(pending);
+                    return;
+                }
+            }
+        }
+
+        // a post-only order must only add liquidity, when it would cross the book on
+        // arrival it is either rejected or slid one tick away from the best opposite price.
+        if let Some(mode) = seq_order.post_only {
+            let slid = slide_price(&self.lob, side, seq_order.price, self.instrument.tick_size);
+            let reason = match (seq_order.order_type, mode, slid) {
+                (OrderType::MARKET, _, _) => Some(RejectReason::WOULD_TAKE_LIQUIDITY),
+                (_, _, None) => None,
+                (_, PostOnly::REJECT, Some(_)) => Some(RejectReason::WOULD_TAKE_LIQUIDITY),
+                (_, PostOnly::SLIDE, Some(price)) if !self.instrument.price_in_range(price) => {
+                    Some(RejectReason::PRICE_OUT_OF_RANGE)
+                }
+                (_, PostOnly::SLIDE, Some(price)) => {
+                    seq_order.price = price;
+                    let repriced =
+                        ExecuteMessage::new(seq_order.seq_id, Execution::REPRICED(price));
+                    events.push(repriced);
+                    None
+                }
+            };
+            if let Some(reason) = reason {
+                let rejected = ExecuteMessage::new(seq_order.seq_id, Execution::REJECTED(reason));
+                events.push(rejected);
+                return;
+            }
+        }
+
+        // a market order takes any price unless it is bounded by a protection price.
+        let limit_price = match seq_order.order_type {
+            OrderType::LIMIT => Some(seq_order.price),
+            OrderType::MARKET => seq_order.protection_price,
+        };
+
+        let expires_at = expiry(
+            seq_order.time_in_force,
+            seq_order.timestamp,
+            self.session_end,
+        );
+        let killed = match seq_order.time_in_force {
+            // a fill or kill order must find enough liquidity before anything is executed.
+            TimeInForce::FOK => !fok_fillable(&self.lob, other_side, limit_price, &seq_order),
+            _ => expires_at.is_some_and(|at| at <= self.clock),
+        };
+        if killed {
+            let expired = ExecuteMessage::new(seq_order.seq_id, Execution::EXPIRED(seq_order.size));
+            events.push(expired);
+            return;
+        }
+
+        // keep consuming the resting orders from the other side, the best order is
+        // always the head of the best limit node so the orders within a limit node are
+        // matched in FIFO order and then the next limit node is picked up.
+        while !seq_order.size.is_zero() {
+            let best = match side {
+                Side::BID => self.lob.best_ask.clone(),
+                Side::ASK => self.lob.best_bid.clone(),
+            };
+
+            let order = match best {
+                Some(order) => order,
+                None => break,
+            };
+
+            let is_match = match limit_price {
+                Some(price) => match order.borrow().side {
+                    Side::ASK => price >= order.borrow().price,
+                    Side::BID => order.borrow().price >= price,
+                },
+                None => true,
+            };
+            // the incoming order does not cross the book anymore.
+            if !is_match {
+                break;
+            }
+
+            // an order never trades against an order of the same owner, the self-trade
+            // prevention mode of the incoming order decides which of the two gives way.
+            let self_trade = seq_order
+                .self_trade_prevention
+                .filter(|_| order.borrow().owner_id == seq_order.owner_id);
+            if let Some(mode) = self_trade {
+                let (passive_seq_id, passive_order_id, price, passive_size) = {
+                    let order = order.borrow();
+                    (
+                        order.seq_id,
+                        order.order_id.clone(),
+                        order.price,
+                        order.size,
+                    )
+                };
+                let smaller = std::cmp::min(passive_size, seq_order.size);
+                let (passive_decrement, aggressor_decrement) = match mode {
+                    SelfTradePrevention::CANCEL_NEWEST => (Qty::ZERO, seq_order.size),
+                    SelfTradePrevention::CANCEL_OLDEST => (passive_size, Qty::ZERO),
+                    SelfTradePrevention::CANCEL_BOTH => (passive_size, seq_order.size),
+                    SelfTradePrevention::DECREMENT_AND_CANCEL => (smaller, smaller),
+                };
+
+                if !passive_decrement.is_zero() {
+                    let leaves = passive_size - passive_decrement;
+                    if leaves.is_zero() {
+                        self.lob.remove(passive_order_id);
+                    } else {
+                        self.lob.amend(&passive_order_id, price, leaves);
+                    }
+                    let prevented = ExecuteMessage::new(
+                        passive_seq_id,
+                        self_trade_execution(passive_decrement, leaves),
+                    );
+                    events.push(prevented);
+                }
+                if !aggressor_decrement.is_zero() {
+                    seq_order.size -= aggressor_decrement;
+                    let prevented = ExecuteMessage::new(
+                        seq_order.seq_id,
+                        self_trade_execution(aggressor_decrement, seq_order.size),
+                    );
+                    events.push(prevented);
+                }
+                continue;
+            }
+
+            // Evalute the quantity to trade, only the displayed peak of an iceberg
+            // order is tradable, its next peak is matched after the orders queued behind it.
+            let quantity_to_trade = std::cmp::min(order.borrow().visible, seq_order.size);
+            let (passive_seq_id, passive_order_id, price) = {
+                let order = order.borrow();
+                (order.seq_id, order.order_id.clone(), order.price)
+            };
+
+            // trade orders
+            self.trade_id += 1;
+            let trade_id = self.trade_id;
+            self.last_price = Some(price);
+            seq_order.size -= quantity_to_trade;
+            cum_qty += quantity_to_trade;
+            // a filled passive order leaves the book and the next best order takes its place.
+            let passive_leaves = self
+                .lob
+                .execute(&passive_order_id, quantity_to_trade)
+                .unwrap_or(Qty::ZERO);
+
+            // emit the trade followed by the execution reports of both orders.
+            let trade = Trade {
+                trade_id,
+                aggressor_order_id: seq_order.order_id.clone(),
+                aggressor_seq_id: seq_order.seq_id,
+                passive_order_id,
+                passive_seq_id,
+                price,
+                qty: quantity_to_trade,
+                aggressor_side: side,
+                timestamp: self.clock,
+            };
+            events.push(ExecuteMessage::new(
+                seq_order.seq_id,
+                Execution::TRADE(trade),
+            ));
+            let passive_fill = Fill {
+                trade_id,
+                price,
+                qty: quantity_to_trade,
+                cum_qty: order.borrow().filled,
+                leaves_qty: passive_leaves,
+            };
+            events.push(ExecuteMessage::new(
+                passive_seq_id,
+                fill_report(passive_fill),
+            ));
+
+            let aggressor_fill = Fill {
+                trade_id,
+                price,
+                qty: quantity_to_trade,
+                cum_qty,
+                leaves_qty: seq_order.size,
+            };
+            events.push(ExecuteMessage::new(
+                seq_order.seq_id,
+                fill_report(aggressor_fill),
+            ));
+        }
+
+        // a fully filled order was already reported by its last fill, and an order
+        // cancelled by self-trade prevention by its cancel.
+        if seq_order.size.is_zero() {
+            return;
+        }
+
+        let rests = seq_order.order_type == OrderType::LIMIT
+            && !matches!(seq_order.time_in_force, TimeInForce::IOC | TimeInForce::FOK);
+
+        let mut outorder_execution = ExecuteMessage::new(seq_order.seq_id, Execution::INSERTED);
+        if !rests {
+            // market and immediate orders never rest in the book, the remaining size expires.
+            outorder_execution.set_execution(Execution::EXPIRED(seq_order.size));
+        } else {
+            if let Some(at) = expires_at {
+                self.expiries
+                    .insert((at, seq_order.seq_id), seq_order.order_id.clone());
+            }
+            // Insert the remaining order, the book updates the best order of its side.
+            let order_id = seq_order.order_id.clone();
+            self.lob.insert(seq_order);
+            if let Some(order) = self.lob.ord_map.get(&order_id) {
+                order.borrow_mut().filled = cum_qty;
+            }
+        }
+
+        // emit execution event.
+        events.push(outorder_execution);
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    fs::{rename, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use core_utils::{
+    Instrument, OrderType, PostOnly, Price, Qty, RawOrder, SelfTradePrevention, Side, TimeInForce,
+};
+use memmap::JournalReader;
+use serde::{Deserialize, Serialize};
+
+use crate::matching::EngineState;
+
+// ---------- ENGINE SNAPSHOT ----------
+
+const MAGIC: u64 = 0x50414E534D454E47; // magic number of a snapshot file.
+
+/// Version of the snapshot format, bumped whenever the layout of `EngineSnapshot` changes.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// A resting order as it was in the book when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestingOrder {
+    pub seq_id: u128,
+    pub order_id: String,
+    pub price: Price,
+    pub size: Qty,    // remaining size of the order, including the hidden reserve.
+    pub visible: Qty, // part of the remaining size shown in the limit node.
+    pub display_size: Option<Qty>,
+    pub filled: Qty,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub post_only: Option<PostOnly>,
+    pub owner_id: u64,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+}
+
+/// The full state of the matching thread after the order with sequence id `last_seq_id`,
+/// the engine restarts from it and replays only the orders sequenced after it.
+///
+/// Layout on disk:
+/// [ magic u64 ][ version u32 ][ bincode encoded snapshot ]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub quote: String,
+    pub last_seq_id: Option<u128>, // `None` when no order was sequenced yet.
+    pub trade_id: u64,             // id of the last trade.
+    pub last_price: Option<Price>, // price of the last trade, the trigger of the stop orders.
+    pub clock: u64,                // the engine clock, the timestamp of the last sequenced order.
+    pub orders: Vec<RestingOrder>, // asks then bids, each side from the best price and in queue order.
+    pub stops: Vec<RawOrder>,      // stop orders waiting for their trigger, in sequence order.
+    pub expiries: Vec<(u64, u128, String)>, // expiry time, sequence id and order id of DAY and GTD orders.
+}
+
+impl EngineSnapshot {
+    /// Takes the snapshot of the state of the matching thread.
+    pub fn capture(state: &EngineState, last_seq_id: Option<u128>) -> Self {
+        let orders = state
+            .lob
+            .resting_orders()
+            .into_iter()
+            .map(|order| RestingOrder {
+                seq_id: order.seq_id,
+                order_id: order.order_id,
+                price: order.price,
+                size: order.size,
+                visible: order.visible,
+                display_size: order.display_size,
+                filled: order.filled,
+                side: order.side,
+                order_type: order.order_type,
+                time_in_force: order.time_in_force,
+                post_only: order.post_only,
+                owner_id: order.owner_id,
+                self_trade_prevention: order.self_trade_prevention,
+            })
+            .collect();
+
+        let mut pending: Vec<RawOrder> = state
+            .stops
+            .bid_stops
+            .values()
+            .chain(state.stops.ask_stops.values())
+            .cloned()
+            .collect();
+        pending.sort_by_key(|order| order.seq_id);
+
+        EngineSnapshot {
+            quote: state.lob.book_id.clone(),
+            last_seq_id,
+            trade_id: state.trade_id,
+            last_price: state.last_price,
+            clock: state.clock,
+            orders,
+            stops: pending,
+            expiries: state
+                .expiries
+                .iter()
+                .map(|((at, seq_id), order_id)| (*at, *seq_id, order_id.clone()))
+                .collect(),
+        }
+    }
+
+    /// Rebuilds the matching state held by the snapshot.
+    pub fn restore(&self, instrument: Instrument, session_end: u64) -> EngineState {
+        let mut state = EngineState::new(self.quote.clone(), instrument, session_end);
+        // the orders are inserted in priority order, so every limit node keeps its queue.
+        for order in &self.orders {
+            let raw_order = RawOrder::default()
+                .with_seq_id(order.seq_id)
+                .with_order_id(order.order_id.clone())
+                .with_quote(self.quote.clone())
+                .with_price(order.price)
+                .with_size(order.size)
+                .with_side(order.side)
+                .with_order_type(order.order_type)
+                .with_time_in_force(order.time_in_force)
+                .with_post_only(order.post_only)
+                .with_display_size(order.display_size)
+                .with_owner_id(order.owner_id)
+                .with_self_trade_prevention(order.self_trade_prevention)
+                .to_owned();
+            state.lob.restore(raw_order, order.visible, order.filled);
+        }
+        // a restored book has no changes to publish, the market data feed starts from a snapshot.
+        state.lob.take_changed_levels();
+
+        for order in &self.stops {
+            state.stops.insert(order.clone());
+        }
+        state.expiries = self
+            .expiries
+            .iter()
+            .map(|(at, seq_id, order_id)| ((*at, *seq_id), order_id.clone()))
+            .collect();
+        state.trade_id = self.trade_id;
+        state.last_price = self.last_price;
+        state.clock = self.clock;
+        state
+    }
+
+    /// Writes the snapshot to a temporary file which then replaces the file at `path`, so a
+    /// crash while writing never leaves a broken snapshot behind.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+
+        let mut file =
+            File::create(&tmp).with_context(|| format!("create snapshot file {:?}", tmp))?;
+        file.write_all(&MAGIC.to_le_bytes())?;
+        file.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        file.write_all(&bincode::serialize(self)?)?;
+        file.sync_all()?;
+
+        rename(&tmp, path).with_context(|| format!("replace snapshot file {:?}", path))?;
+        // the new snapshot only survives a crash once its directory is synced.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("sync snapshot directory {:?}", dir))?;
+        Ok(())
+    }
+
+    /// Reads the snapshot at `path`, a file of another format version is refused.
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path.as_ref())
+            .with_context(|| format!("open snapshot file {:?}", path.as_ref()))?
+            .read_to_end(&mut bytes)?;
+
+        if bytes.len() < 12 {
+            bail!("file is too small to be a snapshot");
+        }
+        let (magic, rest) = bytes.split_at(8);
+        let (version, body) = rest.split_at(4);
+        if u64::from_le_bytes(magic.try_into()?) != MAGIC {
+            bail!("magic mismatch; file is not a snapshot or corrupted");
+        }
+        let version = u32::from_le_bytes(version.try_into()?);
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "unsupported snapshot version {} (expected {})",
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+
+        Ok(bincode::deserialize(body)?)
+    }
+
+    /// Reads the snapshot at `path`, or returns `None` when no snapshot was written yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Option<Self>> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        Self::read(path).map(Some)
+    }
+}
+
+/// Returns the orders of the sequencer journal sequenced after `last_seq_id`, in sequence
+/// order. These are the orders the engine replays on top of its snapshot.
+pub fn sequenced_after<P: AsRef<Path>>(
+    journal: P,
+    last_seq_id: Option<u128>,
+) -> anyhow::Result<Vec<RawOrder>> {
+    let from = last_seq_id.map_or(0, |last| last + 1);
+    let mut orders = Vec::new();
+    for record in JournalReader::open(journal, from)? {
+        let (_, payload) = record?;
+        // an empty record is a request the sequencer rejected, it never reached the engine.
+        if !payload.is_empty() {
+            orders.push(bincode::deserialize(&payload)?);
+        }
+    }
+    Ok(orders)
+}
@@ -1,26 +1,93 @@
-use core_utils::{ExecuteMessage, OrderType, RawOrder, Side};
-use matching_engine::{tmp_path, MatchingEngine};
-use memmap::MmapQueue;
+use core_utils::{
+    Command, ExecuteMessage, Execution, Fill, Instrument, L2Book, Level, MarketData,
+    MarketDataMessage, OrderType, PostOnly, Price, Qty, RawOrder, RejectReason,
+    SelfTradePrevention, Side, TimeInForce, Trade, NANOS_PER_DAY,
+};
+use matching_engine::{
+    expiry,
+    matching::EngineState,
+    snapshot::{sequenced_after, EngineSnapshot},
+    tmp_path, MatchingEngine,
+};
+use memmap::{Journal, MmapQueue, SeqCursor, Subscriber, SyncPolicy};
 use std::fs::remove_file;
+use std::time::{Duration, Instant};
 
-fn create_queues() {
-    let _ = MmapQueue::create(
-        tmp_path("TEST-inbound"),
-        1024,
-        std::mem::size_of::<RawOrder>(),
-    );
-    let _ = MmapQueue::create(
-        tmp_path("TEST-outbound"),
-        1024,
-        std::mem::size_of::<ExecuteMessage>(),
-    );
+// the sequencer creates the queues to and from the engine as rings of records. The files
+// kept across restarts of the engine are removed first, a failed run may have left them.
+fn create_queues(quote: &str) {
+    remove_queues(quote);
+    let _ = MmapQueue::create_records(tmp_path(&format!("{}-inbound", quote)), 1 << 20, 1024);
+    let _ = MmapQueue::create_records(tmp_path(&format!("{}-outbound", quote)), 1 << 20, 1024);
+}
+
+fn remove_queues(quote: &str) {
+    let _ = remove_file(tmp_path(&format!("{}-inbound", quote)));
+    let _ = remove_file(tmp_path(&format!("{}-outbound", quote)));
+    let _ = remove_file(tmp_path(&format!("{}-marketdata", quote)));
+    let _ = remove_file(tmp_path(&format!("{}-marketdata", quote)).with_extension("test.cursor"));
+    let _ = remove_file(std::env::temp_dir().join(format!("{}.progress", quote)));
+}
+
+// the engine runs on its own thread, so wait for the execution to be published.
+fn recv(outbound: &mut MmapQueue) -> Option<ExecuteMessage> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        if let Ok(Some(data)) = outbound.dequeue() {
+            return Some(bincode::deserialize(&data).unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+// the trade events are checked on their own, skip them to look at the execution reports.
+fn recv_report(outbound: &mut MmapQueue) -> Option<ExecuteMessage> {
+    loop {
+        let msg = recv(outbound)?;
+        if !matches!(msg.execution, Execution::TRADE(_)) {
+            return Some(msg);
+        }
+    }
+}
+
+fn recv_market_data(market_data: &mut Subscriber) -> Option<MarketDataMessage> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        if let Ok(Some(data)) = market_data.recv() {
+            return Some(bincode::deserialize(&data).unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+fn fill(trade_id: u64, price: i64, qty: u64, cum_qty: u64, leaves_qty: u64) -> Fill {
+    Fill {
+        trade_id,
+        price: Price(price),
+        qty: Qty(qty),
+        cum_qty: Qty(cum_qty),
+        leaves_qty: Qty(leaves_qty),
+    }
+}
+
+fn order(seq_id: u128, order_id: &str, price: i64, size: u64, side: Side) -> RawOrder {
+    RawOrder::default()
+        .with_seq_id(seq_id)
+        .with_order_id(order_id.into())
+        .with_quote("TEST".into())
+        .with_price(Price(price))
+        .with_size(Qty(size))
+        .with_side(side)
+        .with_order_type(OrderType::LIMIT)
+        .to_owned()
 }
 
 #[test]
 fn test_engine() {
-    create_queues();
+    create_queues("TEST");
 
-    
     let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
     let engine = MatchingEngine::new("TEST".into());
 
@@ -32,17 +99,7 @@ fn test_engine() {
 
     assert!(inbound.is_ok());
 
-    
-
-    let order = RawOrder::default()
-        .with_seq_id(1)
-        .with_order_id("ORDER".into())
-        .with_quote("TEST".into())
-        .with_price(100.10)
-        .with_size(10)
-        .with_side(Side::ASK)
-        .with_order_type(OrderType::LIMIT)
-        .to_owned();
+    let order = order(1, "ORDER", 10010, 10, Side::ASK);
 
     let send = tx.send(order);
     assert!(send.is_ok());
@@ -53,15 +110,1495 @@ fn test_engine() {
 
     assert!(outbound.is_ok());
 
-    let outbound=outbound.unwrap();
+    let outbound = outbound.unwrap();
+
+    let data = recv_report(outbound);
+
+    assert!(data.is_some());
+
+    remove_queues("TEST");
+}
 
-    let data = outbound.dequeue();
+#[test]
+fn test_sweep_multiple_levels() {
+    create_queues("SWEEP");
 
-    assert!(data.is_ok());
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("SWEEP".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
 
-    let data = data.unwrap();
+    // two asks on the first level and one on the next level.
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(3, "ASK3", 10100, 5, Side::ASK)).unwrap();
+    for seq_id in 1..=3 {
+        let msg = recv_report(outbound).unwrap();
+        assert_eq!(msg, ExecuteMessage::new(seq_id, Execution::INSERTED));
+    }
 
-    assert!(data.is_some());
+    // the bid crosses both levels and rests the remaining size.
+    tx.send(order(4, "BID1", 10100, 20, Side::BID)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(1, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 15))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::FILL(fill(2, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(2, 10000, 5, 10, 10))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(3, 10100, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(3, 10100, 5, 15, 5))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::INSERTED))
+    );
+
+    // the resting bid is now the best order and gets partially filled.
+    tx.send(order(5, "ASK4", 9900, 2, Side::ASK)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(4, 10100, 2, 17, 3))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            5,
+            Execution::FILL(fill(4, 10100, 2, 2, 0))
+        ))
+    );
+
+    remove_queues("SWEEP");
+}
+
+#[test]
+fn test_trades() {
+    create_queues("TRADES");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("TRADES".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv(outbound).is_some());
+    }
+
+    // every match publishes a trade with both counterparties before the execution reports.
+    let bid = order(3, "BID1", 10100, 8, Side::BID)
+        .with_timestamp(42)
+        .to_owned();
+    tx.send(bid).unwrap();
+    let trade = |trade_id, passive_order_id: &str, passive_seq_id, price, qty| {
+        Some(ExecuteMessage::new(
+            3,
+            Execution::TRADE(Trade {
+                trade_id,
+                aggressor_order_id: "BID1".into(),
+                aggressor_seq_id: 3,
+                passive_order_id: passive_order_id.into(),
+                passive_seq_id,
+                price: Price(price),
+                qty: Qty(qty),
+                aggressor_side: Side::BID,
+                timestamp: 42,
+            }),
+        ))
+    };
+    assert_eq!(recv(outbound), trade(1, "ASK1", 1, 10000, 5));
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(1, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 3))
+        ))
+    );
+    assert_eq!(recv(outbound), trade(2, "ASK2", 2, 10100, 3));
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::PARTIAL(fill(2, 10100, 3, 3, 2))
+        ))
+    );
+    // the aggressor is fully filled, so no other execution follows its last fill.
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(2, 10100, 3, 8, 0))
+        ))
+    );
+
+    tx.send(order(4, "BID2", 9900, 1, Side::BID)).unwrap();
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(4, Execution::INSERTED))
+    );
+
+    remove_queues("TRADES");
+}
+
+#[test]
+fn test_market_order() {
+    create_queues("MARKET");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("MARKET".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10500, 5, Side::ASK)).unwrap();
+    tx.send(order(3, "ASK3", 11000, 5, Side::ASK)).unwrap();
+    for _ in 1..=3 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // the protection price stops the sweep before the last level and
+    // the rest of the market order expires instead of resting.
+    let market = order(4, "BID1", 0, 20, Side::BID)
+        .with_order_type(OrderType::MARKET)
+        .with_protection_price(Some(Price(10500)))
+        .to_owned();
+    tx.send(market).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(1, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 15))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::FILL(fill(2, 10500, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(2, 10500, 5, 10, 10))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::EXPIRED(Qty(10))))
+    );
+
+    // without protection the market order takes any price.
+    let market = order(5, "BID2", 0, 2, Side::BID)
+        .with_order_type(OrderType::MARKET)
+        .to_owned();
+    tx.send(market).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::PARTIAL(fill(3, 11000, 2, 2, 3))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            5,
+            Execution::FILL(fill(3, 11000, 2, 2, 0))
+        ))
+    );
+
+    // nothing is left on the ask side to take.
+    let market = order(6, "BID3", 0, 10, Side::BID)
+        .with_order_type(OrderType::MARKET)
+        .to_owned();
+    tx.send(market).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(4, 11000, 3, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            6,
+            Execution::PARTIAL(fill(4, 11000, 3, 3, 7))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(6, Execution::EXPIRED(Qty(7))))
+    );
+
+    remove_queues("MARKET");
+}
+
+#[test]
+fn test_time_in_force() {
+    create_queues("TIF");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("TIF".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // not enough liquidity at or below 100.0, so nothing is executed.
+    let fok = order(3, "BID1", 10000, 6, Side::BID)
+        .with_time_in_force(TimeInForce::FOK)
+        .to_owned();
+    tx.send(fok).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(3, Execution::EXPIRED(Qty(6))))
+    );
+
+    // the immediate or cancel order trades what it can and cancels the rest.
+    let ioc = order(4, "BID2", 10000, 6, Side::BID)
+        .with_time_in_force(TimeInForce::IOC)
+        .to_owned();
+    tx.send(ioc).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(1, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 1))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::EXPIRED(Qty(1))))
+    );
+
+    // a good till date order rests until the sequencer clock passes its expiry.
+    let gtd = order(5, "BID3", 9900, 5, Side::BID)
+        .with_time_in_force(TimeInForce::GTD(1_000))
+        .with_timestamp(500)
+        .to_owned();
+    tx.send(gtd).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(5, Execution::INSERTED))
+    );
+
+    let late = order(6, "ASK3", 9900, 5, Side::ASK)
+        .with_timestamp(1_000)
+        .to_owned();
+    tx.send(late).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(5, Execution::EXPIRED(Qty(5))))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(6, Execution::INSERTED))
+    );
+
+    remove_queues("TIF");
+}
+
+#[test]
+fn test_day_expiry() {
+    let session_end = 16 * 60 * 60 * 1_000_000_000;
+    let morning = 3 * NANOS_PER_DAY + 9 * 60 * 60 * 1_000_000_000;
+    let evening = 3 * NANOS_PER_DAY + 20 * 60 * 60 * 1_000_000_000;
+
+    assert_eq!(
+        expiry(TimeInForce::DAY, morning, session_end),
+        Some(3 * NANOS_PER_DAY + session_end)
+    );
+    // orders entered after the session end belong to the next session.
+    assert_eq!(
+        expiry(TimeInForce::DAY, evening, session_end),
+        Some(4 * NANOS_PER_DAY + session_end)
+    );
+    assert_eq!(expiry(TimeInForce::GTD(42), morning, session_end), Some(42));
+    assert_eq!(expiry(TimeInForce::GTC, morning, session_end), None);
+}
+
+#[test]
+fn test_cancel() {
+    create_queues("CANCEL");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("CANCEL".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 10, Side::ASK)).unwrap();
+    tx.send(order(2, "BID1", 10000, 4, Side::BID)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(1, Execution::INSERTED))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::PARTIAL(fill(1, 10000, 4, 4, 6))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::FILL(fill(1, 10000, 4, 4, 0))
+        ))
+    );
+
+    // the cancel reports the remaining quantity of the resting order.
+    let mut cancel = RawOrder::default()
+        .with_seq_id(3)
+        .with_order_id("ASK1".into())
+        .with_command(Command::CANCEL)
+        .to_owned();
+    tx.send(cancel.clone()).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(3, Execution::CANCELLED(Qty(6))))
+    );
+
+    // the order is gone now, so a second cancel is rejected.
+    tx.send(cancel.with_seq_id(4).to_owned()).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::REJECTED(RejectReason::UNKNOWN_ORDER)
+        ))
+    );
+
+    // and the cancelled order can't be matched anymore.
+    tx.send(order(5, "BID2", 10000, 4, Side::BID)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(5, Execution::INSERTED))
+    );
+
+    remove_queues("CANCEL");
+}
+
+#[test]
+fn test_amend() {
+    create_queues("AMEND");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("AMEND".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    let amend = |seq_id: u128, order_id: &str, price: i64, size: u64| {
+        RawOrder::default()
+            .with_seq_id(seq_id)
+            .with_order_id(order_id.into())
+            .with_price(Price(price))
+            .with_size(Qty(size))
+            .with_command(Command::AMEND)
+            .to_owned()
+    };
+
+    tx.send(order(1, "ASK1", 10100, 10, Side::ASK)).unwrap();
+    tx.send(order(2, "BID1", 9900, 10, Side::BID)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // decreasing the size keeps the order resting at the same price.
+    tx.send(amend(3, "ASK1", 10100, 6)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::AMENDED(Price(10100), Qty(6))
+        ))
+    );
+
+    // the new price of the bid crosses the ask, so the bid is matched again.
+    tx.send(amend(4, "BID1", 10100, 10)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::AMENDED(Price(10100), Qty(10))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(1, 10100, 6, 6, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::PARTIAL(fill(1, 10100, 6, 6, 4))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(2, Execution::INSERTED))
+    );
+
+    // the remaining bid rests at the new price.
+    tx.send(order(5, "ASK2", 10100, 2, Side::ASK)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::PARTIAL(fill(2, 10100, 2, 8, 2))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            5,
+            Execution::FILL(fill(2, 10100, 2, 2, 0))
+        ))
+    );
+
+    tx.send(amend(6, "ASK1", 10100, 6)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            6,
+            Execution::REJECTED(RejectReason::UNKNOWN_ORDER)
+        ))
+    );
+    tx.send(amend(7, "BID1", 10100, 0)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            7,
+            Execution::REJECTED(RejectReason::INVALID_SIZE)
+        ))
+    );
+
+    remove_queues("AMEND");
+}
+
+#[test]
+fn test_amend_across_spread_keeps_time_in_force() {
+    let session_end = NANOS_PER_DAY / 2;
+    let mut state = EngineState::new("AMENDDAY".into(), Instrument::new("AMENDDAY"), session_end);
+
+    state.process(order(1, "ASK1", 10100, 5, Side::ASK));
+    let day = order(2, "BID1", 9900, 10, Side::BID)
+        .with_time_in_force(TimeInForce::DAY)
+        .with_timestamp(100)
+        .to_owned();
+    state.process(day);
+
+    // the amended bid crosses the ask, trades and rests the remaining size.
+    let amend = order(3, "BID1", 10100, 10, Side::BID)
+        .with_command(Command::AMEND)
+        .with_timestamp(200)
+        .to_owned();
+    let executions = state.process(amend);
+    assert_eq!(
+        executions.last(),
+        Some(&ExecuteMessage::new(2, Execution::INSERTED))
+    );
+    assert_eq!(
+        state.lob.ord_map["BID1"].borrow().time_in_force,
+        TimeInForce::DAY
+    );
+
+    // the rested bid is still a DAY order, so it expires at the end of the session.
+    let late = order(4, "ASK2", 10200, 5, Side::ASK)
+        .with_timestamp(session_end)
+        .to_owned();
+    assert_eq!(
+        state.process(late),
+        vec![
+            ExecuteMessage::new(2, Execution::EXPIRED(Qty(5))),
+            ExecuteMessage::new(4, Execution::INSERTED),
+        ]
+    );
+    assert!(!state.lob.ord_map.contains_key("BID1"));
+}
+
+#[test]
+fn test_iceberg_replenish_keeps_time_in_force() {
+    let mut state = EngineState::new(
+        "ICEBERGGTD".into(),
+        Instrument::new("ICEBERGGTD"),
+        NANOS_PER_DAY,
+    );
+
+    let iceberg = order(1, "ASK1", 10000, 10, Side::ASK)
+        .with_display_size(Some(Qty(4)))
+        .with_time_in_force(TimeInForce::GTD(1_000))
+        .to_owned();
+    state.process(iceberg);
+
+    // taking the whole peak replenishes it from the reserve at the back of the queue.
+    state.process(order(2, "BID1", 10000, 4, Side::BID));
+    let resting = state.lob.resting_orders();
+    assert_eq!(resting.len(), 1);
+    assert_eq!(resting[0].visible, Qty(4));
+    assert_eq!(resting[0].time_in_force, TimeInForce::GTD(1_000));
+
+    // the replenished order still expires at its time.
+    let late = order(3, "BID2", 9900, 1, Side::BID)
+        .with_timestamp(1_000)
+        .to_owned();
+    assert_eq!(
+        state.process(late),
+        vec![
+            ExecuteMessage::new(1, Execution::EXPIRED(Qty(6))),
+            ExecuteMessage::new(3, Execution::INSERTED),
+        ]
+    );
+}
+
+#[test]
+fn test_post_only() {
+    create_queues("POSTONLY");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("POSTONLY".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    assert!(recv_report(outbound).is_some());
+
+    // the post-only bid would take the ask, so it is rejected without trading.
+    let reject = order(2, "BID1", 10000, 5, Side::BID)
+        .with_post_only(Some(PostOnly::REJECT))
+        .to_owned();
+    tx.send(reject).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::REJECTED(RejectReason::WOULD_TAKE_LIQUIDITY)
+        ))
+    );
+
+    // the sliding bid rests one tick below the best ask instead.
+    let slide = order(3, "BID2", 10100, 5, Side::BID)
+        .with_post_only(Some(PostOnly::SLIDE))
+        .to_owned();
+    tx.send(slide).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(3, Execution::REPRICED(Price(9999))))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(3, Execution::INSERTED))
+    );
+
+    // a post-only order that does not cross rests at its own price.
+    let passive = order(4, "BID3", 9990, 5, Side::BID)
+        .with_post_only(Some(PostOnly::REJECT))
+        .to_owned();
+    tx.send(passive).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::INSERTED))
+    );
+
+    // amending it into the ask is rejected and the order keeps resting.
+    let amend = RawOrder::default()
+        .with_seq_id(5)
+        .with_order_id("BID3".into())
+        .with_price(Price(10000))
+        .with_size(Qty(5))
+        .with_command(Command::AMEND)
+        .to_owned();
+    tx.send(amend).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            5,
+            Execution::REJECTED(RejectReason::WOULD_TAKE_LIQUIDITY)
+        ))
+    );
+
+    // the slid bid is the best bid and is taken at its new price.
+    tx.send(order(6, "ASK2", 9990, 5, Side::ASK)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(1, 9999, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            6,
+            Execution::FILL(fill(1, 9999, 5, 5, 0))
+        ))
+    );
+
+    // reduce-only orders are refused until the engine tracks positions.
+    let reduce_only = order(7, "ASK3", 10000, 5, Side::ASK)
+        .with_reduce_only(true)
+        .to_owned();
+    tx.send(reduce_only).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            7,
+            Execution::REJECTED(RejectReason::UNSUPPORTED)
+        ))
+    );
+
+    remove_queues("POSTONLY");
+}
+
+#[test]
+fn test_iceberg() {
+    create_queues("ICEBERG");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("ICEBERG".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    let iceberg = order(1, "ASK1", 10000, 12, Side::ASK)
+        .with_display_size(Some(Qty(5)))
+        .to_owned();
+    tx.send(iceberg).unwrap();
+    tx.send(order(2, "ASK2", 10000, 5, Side::ASK)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // the first peak is taken, then the replenished peak waits behind the second ask.
+    tx.send(order(3, "BID1", 10000, 14, Side::BID)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 7))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::PARTIAL(fill(1, 10000, 5, 5, 9))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            2,
+            Execution::FILL(fill(2, 10000, 5, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::PARTIAL(fill(2, 10000, 5, 10, 4))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::PARTIAL(fill(3, 10000, 4, 9, 3))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(3, 10000, 4, 14, 0))
+        ))
+    );
+
+    // cancelling reports the displayed and the hidden quantity left.
+    let cancel = RawOrder::default()
+        .with_seq_id(4)
+        .with_order_id("ASK1".into())
+        .with_command(Command::CANCEL)
+        .to_owned();
+    tx.send(cancel).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::CANCELLED(Qty(3))))
+    );
+
+    remove_queues("ICEBERG");
+}
+
+#[test]
+fn test_stop_orders() {
+    create_queues("STOP");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("STOP".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    tx.send(order(3, "ASK3", 10200, 5, Side::ASK)).unwrap();
+    for _ in 1..=3 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // the stop orders wait outside the book, a stop market and a stop limit order.
+    let stop_market = order(4, "STOP1", 0, 5, Side::BID)
+        .with_order_type(OrderType::MARKET)
+        .with_stop_price(Some(Price(10000)))
+        .to_owned();
+    let stop_limit = order(5, "STOP2", 10200, 5, Side::BID)
+        .with_stop_price(Some(Price(10100)))
+        .to_owned();
+    tx.send(stop_market).unwrap();
+    tx.send(stop_limit).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(4, Execution::PENDING))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(5, Execution::PENDING))
+    );
+
+    // the trade at 100.00 triggers the first stop, which trades at 101.00 and triggers the second.
+    tx.send(order(6, "BID1", 10000, 1, Side::BID)).unwrap();
+    let expected = [
+        (1, Execution::PARTIAL(fill(1, 10000, 1, 1, 4))),
+        (6, Execution::FILL(fill(1, 10000, 1, 1, 0))),
+        (4, Execution::TRIGGERED(Price(10000))),
+        (1, Execution::FILL(fill(2, 10000, 4, 5, 0))),
+        (4, Execution::PARTIAL(fill(2, 10000, 4, 4, 1))),
+        (2, Execution::PARTIAL(fill(3, 10100, 1, 1, 4))),
+        (4, Execution::FILL(fill(3, 10100, 1, 5, 0))),
+        (5, Execution::TRIGGERED(Price(10100))),
+        (2, Execution::FILL(fill(4, 10100, 4, 5, 0))),
+        (5, Execution::PARTIAL(fill(4, 10100, 4, 4, 1))),
+        (3, Execution::PARTIAL(fill(5, 10200, 1, 1, 4))),
+        (5, Execution::FILL(fill(5, 10200, 1, 5, 0))),
+    ];
+    for (seq_id, execution) in expected {
+        assert_eq!(
+            recv_report(outbound),
+            Some(ExecuteMessage::new(seq_id, execution))
+        );
+    }
+
+    // a pending stop order can be cancelled.
+    let stop_sell = order(7, "STOP3", 9000, 5, Side::ASK)
+        .with_stop_price(Some(Price(9000)))
+        .to_owned();
+    tx.send(stop_sell).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(7, Execution::PENDING))
+    );
+    let cancel = RawOrder::default()
+        .with_seq_id(8)
+        .with_order_id("STOP3".into())
+        .with_command(Command::CANCEL)
+        .to_owned();
+    tx.send(cancel).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(8, Execution::CANCELLED(Qty(5))))
+    );
+
+    remove_queues("STOP");
+}
+
+#[test]
+fn test_self_trade_prevention() {
+    create_queues("STP");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("STP".into()).unwrap();
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    let owned = |seq_id, order_id: &str, size, side, owner_id, mode| {
+        order(seq_id, order_id, 10000, size, side)
+            .with_owner_id(owner_id)
+            .with_self_trade_prevention(mode)
+            .to_owned()
+    };
+    let report = |seq_id, execution| Some(ExecuteMessage::new(seq_id, execution));
+
+    tx.send(owned(1, "ASK1", 5, Side::ASK, 7, None)).unwrap();
+    tx.send(owned(2, "ASK2", 5, Side::ASK, 8, None)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv_report(outbound).is_some());
+    }
+
+    // cancel newest, the incoming order is cancelled and the book is left untouched.
+    let mode = Some(SelfTradePrevention::CANCEL_NEWEST);
+    tx.send(owned(3, "BID1", 3, Side::BID, 7, mode)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        report(3, Execution::SELF_TRADE_CANCELLED(Qty(3)))
+    );
+
+    // cancel oldest, the resting order is cancelled and the incoming order trades on.
+    let mode = Some(SelfTradePrevention::CANCEL_OLDEST);
+    tx.send(owned(4, "BID2", 3, Side::BID, 7, mode)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        report(1, Execution::SELF_TRADE_CANCELLED(Qty(5)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(2, Execution::PARTIAL(fill(1, 10000, 3, 3, 2)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(4, Execution::FILL(fill(1, 10000, 3, 3, 0)))
+    );
+
+    // cancel both.
+    tx.send(owned(5, "ASK3", 4, Side::ASK, 7, None)).unwrap();
+    assert_eq!(recv_report(outbound), report(5, Execution::INSERTED));
+    let mode = Some(SelfTradePrevention::CANCEL_BOTH);
+    tx.send(owned(6, "BID3", 1, Side::BID, 8, mode)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        report(2, Execution::SELF_TRADE_CANCELLED(Qty(2)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(6, Execution::SELF_TRADE_CANCELLED(Qty(1)))
+    );
+
+    // decrement and cancel, the smaller resting order is cancelled and the rest of the
+    // incoming order rests in the book.
+    let mode = Some(SelfTradePrevention::DECREMENT_AND_CANCEL);
+    tx.send(owned(7, "BID4", 6, Side::BID, 7, mode)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        report(5, Execution::SELF_TRADE_CANCELLED(Qty(4)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(7, Execution::SELF_TRADE_DECREMENTED(Qty(4), Qty(2)))
+    );
+    assert_eq!(recv_report(outbound), report(7, Execution::INSERTED));
+
+    remove_queues("STP");
+}
+
+#[test]
+fn test_fill_or_kill_self_trade() {
+    let mut state = EngineState::new("FOKSTP".into(), Instrument::new("FOKSTP"), NANOS_PER_DAY);
+    let fok = |seq_id, mode| {
+        order(seq_id, "BID", 10000, 8, Side::BID)
+            .with_time_in_force(TimeInForce::FOK)
+            .with_owner_id(7)
+            .with_self_trade_prevention(Some(mode))
+            .to_owned()
+    };
+
+    state.process(
+        order(1, "ASK1", 10000, 5, Side::ASK)
+            .with_owner_id(7)
+            .to_owned(),
+    );
+    state.process(
+        order(2, "ASK2", 10000, 5, Side::ASK)
+            .with_owner_id(8)
+            .to_owned(),
+    );
+
+    // the book holds 10, but 5 of them belong to the owner of the order.
+    for (seq_id, mode) in [
+        (3, SelfTradePrevention::CANCEL_OLDEST),
+        (4, SelfTradePrevention::DECREMENT_AND_CANCEL),
+        (5, SelfTradePrevention::CANCEL_NEWEST),
+    ] {
+        assert_eq!(
+            state.process(fok(seq_id, mode)),
+            vec![ExecuteMessage::new(seq_id, Execution::EXPIRED(Qty(8)))]
+        );
+    }
+    assert_eq!(state.lob.liquidity(Side::ASK, None, Qty(100)), Qty(10));
+
+    // cancelling the resting order of the owner leaves the size of the order untouched.
+    state.process(
+        order(6, "ASK3", 10000, 5, Side::ASK)
+            .with_owner_id(8)
+            .to_owned(),
+    );
+    let executions = state.process(fok(7, SelfTradePrevention::CANCEL_OLDEST));
+    assert_eq!(
+        executions[0],
+        ExecuteMessage::new(1, Execution::SELF_TRADE_CANCELLED(Qty(5)))
+    );
+    assert_eq!(
+        executions.last(),
+        Some(&ExecuteMessage::new(
+            7,
+            Execution::FILL(fill(2, 10000, 3, 8, 0))
+        ))
+    );
+
+    // an order of the owner queued ahead of enough liquidity would decrement the order.
+    state.process(
+        order(8, "ASK4", 10000, 5, Side::ASK)
+            .with_owner_id(7)
+            .to_owned(),
+    );
+    state.process(
+        order(9, "ASK5", 10000, 10, Side::ASK)
+            .with_owner_id(8)
+            .to_owned(),
+    );
+    assert_eq!(
+        state.process(fok(10, SelfTradePrevention::DECREMENT_AND_CANCEL)),
+        vec![ExecuteMessage::new(10, Execution::EXPIRED(Qty(8)))]
+    );
+}
+
+#[test]
+fn test_instrument_validation() {
+    create_queues("INSTRUMENT");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("INSTRUMENT".into()).unwrap();
+    engine.instrument = Instrument::load("instruments.json")
+        .unwrap()
+        .into_iter()
+        .find(|instrument| instrument.symbol == "BTCETH")
+        .unwrap();
+    engine.instrument.tick_size = Price(5);
+    engine.instrument.lot_size = Qty(10);
+    engine.instrument.max_size = Qty(100);
+    engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ORDER1", 10003, 10, Side::BID)).unwrap();
+    tx.send(order(2, "ORDER2", 10005, 15, Side::BID)).unwrap();
+    tx.send(order(3, "ORDER3", 10005, 200, Side::BID)).unwrap();
+    tx.send(order(4, "ORDER4", 0, 10, Side::BID)).unwrap();
+    tx.send(order(5, "ORDER5", 10005, 10, Side::BID)).unwrap();
+
+    let rejected = |seq_id, reason| Some(ExecuteMessage::new(seq_id, Execution::REJECTED(reason)));
+    assert_eq!(recv_report(outbound), rejected(1, RejectReason::OFF_TICK));
+    assert_eq!(recv_report(outbound), rejected(2, RejectReason::OFF_LOT));
+    assert_eq!(
+        recv_report(outbound),
+        rejected(3, RejectReason::SIZE_OUT_OF_RANGE)
+    );
+    assert_eq!(
+        recv_report(outbound),
+        rejected(4, RejectReason::PRICE_OUT_OF_RANGE)
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(5, Execution::INSERTED))
+    );
+
+    remove_queues("INSTRUMENT");
+}
+
+#[test]
+fn test_market_data() {
+    create_queues("MD");
+    let path = std::env::temp_dir().join("MD.snapshot.dat");
+    let _ = remove_file(&path);
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("MD".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    let handle = engine.run(rx).unwrap();
+    let mut market_data = Subscriber::open(tmp_path("MD-marketdata"), "test").unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    tx.send(order(3, "BID1", 10000, 3, Side::BID)).unwrap();
+
+    let level = |price, vol, orders| Level {
+        price: Price(price),
+        vol: Qty(vol),
+        orders,
+    };
+    // the feed starts with a snapshot of the empty book.
+    let expected = [
+        (0, MarketData::SNAPSHOT(0)),
+        (
+            0,
+            MarketData::TOP_OF_BOOK {
+                bid: None,
+                ask: None,
+            },
+        ),
+        (1, MarketData::ADD(Side::ASK, level(10000, 5, 1))),
+        (
+            1,
+            MarketData::TOP_OF_BOOK {
+                bid: None,
+                ask: Some(level(10000, 5, 1)),
+            },
+        ),
+        (2, MarketData::ADD(Side::ASK, level(10100, 5, 1))),
+        (
+            3,
+            MarketData::TRADE {
+                trade_id: 1,
+                price: Price(10000),
+                qty: Qty(3),
+                aggressor_side: Side::BID,
+                timestamp: 0,
+            },
+        ),
+        (3, MarketData::UPDATE(Side::ASK, level(10000, 2, 1))),
+        (
+            3,
+            MarketData::TOP_OF_BOOK {
+                bid: None,
+                ask: Some(level(10000, 2, 1)),
+            },
+        ),
+    ];
+
+    // a consumer rebuilds the book from the snapshot and the deltas.
+    let mut book = L2Book::new("MD");
+    for (msg_seq, (seq_id, event)) in expected.into_iter().enumerate() {
+        let msg = recv_market_data(&mut market_data).unwrap();
+        assert_eq!(
+            msg,
+            MarketDataMessage::new(msg_seq as u64 + 1, seq_id, event)
+        );
+        book.apply(&msg).unwrap();
+    }
+    assert!(book.is_synced());
+    assert_eq!(
+        book.snapshot(10).asks,
+        vec![level(10000, 2, 1), level(10100, 5, 1)]
+    );
+    assert!(book.bids.is_empty());
+
+    let published = book.msg_seq;
+
+    // a missed message is reported as a gap.
+    let skipped = MarketDataMessage::new(
+        book.msg_seq + 2,
+        4,
+        MarketData::DELETE(Side::ASK, Price(10100)),
+    );
+    assert!(book.apply(&skipped).is_err());
+    assert!(!book.is_synced());
+
+    // a restarted engine keeps the feed, the subscriber carries on from its place and the
+    // snapshot published at startup continues the message sequence and resyncs the book.
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    drop(engine);
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("MD".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    let handle = engine.run(rx).unwrap();
+    let snapshot = recv_market_data(&mut market_data).unwrap();
+    assert_eq!(
+        snapshot,
+        MarketDataMessage::new(published + 1, 3, MarketData::SNAPSHOT(2))
+    );
+    book.apply(&snapshot).unwrap();
+    // the two levels of the restored book and the top of the book.
+    for _ in 0..3 {
+        let msg = recv_market_data(&mut market_data).unwrap();
+        book.apply(&msg).unwrap();
+    }
+    assert!(book.is_synced());
+    assert_eq!(
+        book.snapshot(10).asks,
+        vec![level(10000, 2, 1), level(10100, 5, 1)]
+    );
+    drop(tx);
+    handle.join().unwrap().unwrap();
+
+    let _ = remove_file(&path);
+    remove_queues("MD");
+}
+
+#[test]
+fn test_snapshot_restore() {
+    create_queues("RESTORE");
+    let path = std::env::temp_dir().join("RESTORE.snapshot.dat");
+    let _ = remove_file(&path);
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("RESTORE".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    assert_eq!(
+        engine.progress_path(),
+        Some(std::env::temp_dir().join("RESTORE.progress"))
+    );
+    let handle = engine.run(rx).unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    let iceberg = order(2, "ASK2", 10000, 10, Side::ASK)
+        .with_display_size(Some(Qty(4)))
+        .to_owned();
+    tx.send(iceberg).unwrap();
+    tx.send(order(3, "BID1", 10000, 7, Side::BID)).unwrap();
+    let stop = order(4, "STOP1", 10200, 1, Side::BID)
+        .with_stop_price(Some(Price(10100)))
+        .to_owned();
+    tx.send(stop).unwrap();
+    let gtd = order(5, "ASK3", 10300, 1, Side::ASK)
+        .with_time_in_force(TimeInForce::GTD(u64::MAX))
+        .to_owned();
+    tx.send(gtd).unwrap();
+
+    // the engine saves its state when it stops.
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    let outbound = engine.get_outbound().unwrap();
+    while outbound.dequeue().unwrap().is_some() {}
+    drop(engine);
+
+    let snapshot = EngineSnapshot::read(&path).unwrap();
+    assert_eq!(snapshot.last_seq_id, Some(5));
+    assert_eq!(snapshot.trade_id, 2);
+    assert_eq!(snapshot.last_price, Some(Price(10000)));
+    let orders: Vec<_> = snapshot
+        .orders
+        .iter()
+        .map(|order| {
+            (
+                order.order_id.as_str(),
+                order.size,
+                order.visible,
+                order.filled,
+            )
+        })
+        .collect();
+    assert_eq!(
+        orders,
+        vec![
+            ("ASK2", Qty(8), Qty(2), Qty(2)),
+            ("ASK3", Qty(1), Qty(1), Qty::ZERO)
+        ]
+    );
+    assert_eq!(snapshot.stops.len(), 1);
+    assert_eq!(snapshot.expiries, vec![(u64::MAX, 5, "ASK3".to_string())]);
+
+    // a restarted engine continues from the snapshot, the partially taken peak of the iceberg
+    // is traded first and the trade ids carry on.
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("RESTORE".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    let handle = engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    // an order resent after a crash of the sequencer was already processed and is skipped.
+    tx.send(order(5, "ASK3", 10300, 1, Side::ASK)).unwrap();
+    tx.send(order(6, "BID2", 10000, 5, Side::BID)).unwrap();
+    let report = |seq_id, execution| Some(ExecuteMessage::new(seq_id, execution));
+    assert_eq!(
+        recv_report(outbound),
+        report(2, Execution::PARTIAL(fill(3, 10000, 2, 4, 6)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(6, Execution::PARTIAL(fill(3, 10000, 2, 2, 3)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(2, Execution::PARTIAL(fill(4, 10000, 3, 7, 3)))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        report(6, Execution::FILL(fill(4, 10000, 3, 5, 0)))
+    );
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    assert!(outbound.dequeue().unwrap().is_none());
+    let progress = SeqCursor::open(std::env::temp_dir().join("RESTORE.progress")).unwrap();
+    assert_eq!(progress.load(), Some(6));
+
+    // a snapshot of another format version is refused.
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[8] += 1;
+    let unsupported = std::env::temp_dir().join("RESTORE.unsupported.dat");
+    std::fs::write(&unsupported, bytes).unwrap();
+    assert!(EngineSnapshot::read(&unsupported).is_err());
+
+    // only the orders sequenced after the snapshot are replayed from the sequencer journal.
+    let dir = std::env::temp_dir().join("RESTORE.journal");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS).unwrap();
+    for seq_id in 4..=6 {
+        let order = order(seq_id, &format!("ORDER{seq_id}"), 10000, 1, Side::BID);
+        journal
+            .append(seq_id, &bincode::serialize(&order).unwrap())
+            .unwrap();
+    }
+    // a request rejected by the sequencer only takes up its sequence id.
+    journal.append(7, &[]).unwrap();
+    let replayed: Vec<_> = sequenced_after(&dir, Some(5))
+        .unwrap()
+        .iter()
+        .map(|order| order.seq_id)
+        .collect();
+    assert_eq!(replayed, vec![6]);
+    assert_eq!(sequenced_after(&dir, None).unwrap().len(), 3);
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&unsupported);
+    let _ = std::fs::remove_dir_all(&dir);
+    remove_queues("RESTORE");
+}
+
+#[test]
+fn test_snapshot_interval() {
+    create_queues("INTERVAL");
+    let path = std::env::temp_dir().join("INTERVAL.snapshot.dat");
+    let _ = remove_file(&path);
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("INTERVAL".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    engine.book_snapshot_interval = 2;
+    let handle = engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    // the orders sent again are skipped, they don't count towards the next snapshot.
+    for _ in 0..3 {
+        tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    }
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(1, Execution::INSERTED))
+    );
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!path.exists());
+
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(2, Execution::INSERTED))
+    );
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !path.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(EngineSnapshot::read(&path).unwrap().last_seq_id, Some(2));
+
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    let _ = remove_file(&path);
+    remove_queues("INTERVAL");
+}
+
+#[test]
+fn test_restart_reports_once() {
+    create_queues("CRASH");
+    let path = std::env::temp_dir().join("CRASH.snapshot.dat");
+    let _ = remove_file(&path);
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("CRASH".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    engine.book_snapshot_interval = 2;
+    let handle = engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+
+    tx.send(order(1, "ASK1", 10000, 5, Side::ASK)).unwrap();
+    tx.send(order(2, "ASK2", 10100, 5, Side::ASK)).unwrap();
+    for _ in 1..=2 {
+        assert!(recv_report(outbound).is_some());
+    }
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !path.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let saved = std::fs::read(&path).unwrap();
+
+    // the bid is reported, then the engine crashes before it saves its state again.
+    tx.send(order(3, "BID1", 10000, 3, Side::BID)).unwrap();
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::PARTIAL(fill(1, 10000, 3, 3, 2))
+        ))
+    );
+    assert_eq!(
+        recv_report(outbound),
+        Some(ExecuteMessage::new(
+            3,
+            Execution::FILL(fill(1, 10000, 3, 3, 0))
+        ))
+    );
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    drop(engine);
+    std::fs::write(&path, saved).unwrap();
+    assert_eq!(EngineSnapshot::read(&path).unwrap().last_seq_id, Some(2));
+
+    // the restarted engine gets the bid again from the journal, it rebuilds its state from it
+    // without reporting it twice.
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let mut engine = MatchingEngine::new("CRASH".into()).unwrap();
+    engine.book_snapshot_path = Some(path.clone());
+    let handle = engine.run(rx).unwrap();
+    let outbound = engine.get_outbound().unwrap();
+    tx.send(order(3, "BID1", 10000, 3, Side::BID)).unwrap();
+    tx.send(order(4, "BID2", 10000, 2, Side::BID)).unwrap();
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::TRADE(Trade {
+                trade_id: 2,
+                aggressor_order_id: "BID2".into(),
+                aggressor_seq_id: 4,
+                passive_order_id: "ASK1".into(),
+                passive_seq_id: 1,
+                price: Price(10000),
+                qty: Qty(2),
+                aggressor_side: Side::BID,
+                timestamp: 0,
+            })
+        ))
+    );
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            1,
+            Execution::FILL(fill(2, 10000, 2, 5, 0))
+        ))
+    );
+    assert_eq!(
+        recv(outbound),
+        Some(ExecuteMessage::new(
+            4,
+            Execution::FILL(fill(2, 10000, 2, 2, 0))
+        ))
+    );
+    drop(tx);
+    handle.join().unwrap().unwrap();
+    assert!(outbound.dequeue().unwrap().is_none());
+
+    let _ = remove_file(&path);
+    remove_queues("CRASH");
+}
+
+#[test]
+fn test_replay() {
+    create_queues("REPLAY");
+
+    let (tx, rx) = crossbeam::channel::unbounded::<RawOrder>();
+    let engine = MatchingEngine::new("REPLAY".into()).unwrap();
+    let handle = engine.run(rx).unwrap();
+
+    let orders = vec![
+        order(1, "ASK1", 10000, 5, Side::ASK),
+        order(2, "ASK2", 10100, 5, Side::ASK)
+            .with_display_size(Some(Qty(2)))
+            .to_owned(),
+        order(3, "STOP1", 0, 4, Side::BID)
+            .with_order_type(OrderType::MARKET)
+            .with_stop_price(Some(Price(10000)))
+            .to_owned(),
+        order(4, "BID1", 10000, 2, Side::BID),
+        order(5, "ASK2", 10200, 5, Side::ASK)
+            .with_command(Command::AMEND)
+            .to_owned(),
+        order(6, "ASK1", 0, 0, Side::ASK)
+            .with_command(Command::CANCEL)
+            .to_owned(),
+    ];
+    for order in orders.iter().cloned() {
+        tx.send(order).unwrap();
+    }
+    drop(tx);
+    handle.join().unwrap().unwrap();
+
+    let outbound = engine.get_outbound().unwrap();
+    let mut emitted = Vec::new();
+    while let Some(data) = outbound.dequeue().unwrap() {
+        emitted.push(bincode::deserialize::<ExecuteMessage>(&data).unwrap());
+    }
+
+    // the same orders applied to a fresh state yield exactly the executions of the engine.
+    let mut state = EngineState::new("REPLAY".into(), Instrument::new("REPLAY"), NANOS_PER_DAY);
+    let replayed: Vec<ExecuteMessage> = orders
+        .into_iter()
+        .flat_map(|order| state.process(order))
+        .collect();
+    assert!(emitted
+        .iter()
+        .any(|msg| matches!(msg.execution, Execution::TRIGGERED(_))));
+    assert_eq!(emitted, replayed);
 
-    let _ = remove_file(tmp_path("TEST-inbound"));
+    remove_queues("REPLAY");
 }
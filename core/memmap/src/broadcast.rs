@@ -0,0 +1,144 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::atomic::{fence, Ordering},
+};
+
+use anyhow::{bail, Ok, Result};
+
+use crate::{Consumer, MmapQueue, QueueMode, SeqCursor};
+
+/// Returned when the producer of a broadcast ring overwrote messages a subscriber had not
+/// read yet. The subscriber skips to the oldest message left, so the next `recv` goes on
+/// from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lapped {
+    pub expected: u64, // index of the message the subscriber wanted to read.
+    pub oldest: u64,   // index of the oldest message still in the ring.
+}
+
+impl Display for Lapped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "subscriber lapped by the producer, missed messages {} to {}",
+            self.expected,
+            self.oldest - 1
+        )
+    }
+}
+
+impl std::error::Error for Lapped {}
+
+/// An independent reader of a broadcast queue. Every subscriber keeps its own cursor in a
+/// sidecar file next to the queue, so it picks up where it stopped after a restart.
+/// ```rust
+/// use memmap::{Lapped, MmapQueue, QueueMode, Subscriber};
+///
+/// let path = std::env::temp_dir().join("broadcast_doc.dat");
+/// let mut queue = MmapQueue::create_with_mode(&path, 4, 16, QueueMode::BROADCAST).unwrap();
+/// let mut risk = Subscriber::open(&path, "risk").unwrap();
+///
+/// queue.enqueue(b"ORDER1").unwrap();
+/// let mut drop_copy = Subscriber::open(&path, "drop-copy").unwrap();
+/// assert_eq!(risk.recv().unwrap(), Some(b"ORDER1".to_vec()));
+/// assert_eq!(drop_copy.recv().unwrap(), Some(b"ORDER1".to_vec()));
+///
+/// // the producer never waits, a slow subscriber is told what it missed.
+/// for i in 2..=6 {
+///     queue.enqueue(format!("ORDER{i}").as_bytes()).unwrap();
+/// }
+/// let err = risk.recv().unwrap_err().downcast::<Lapped>().unwrap();
+/// assert_eq!(err, Lapped { expected: 1, oldest: 2 });
+/// assert_eq!(risk.recv().unwrap(), Some(b"ORDER3".to_vec()));
+/// # let _ = std::fs::remove_file(&path);
+/// # let _ = std::fs::remove_file(path.with_extension("risk.cursor"));
+/// # let _ = std::fs::remove_file(path.with_extension("drop-copy.cursor"));
+/// ```
+pub struct Subscriber {
+    queue: MmapQueue,
+    cursor: SeqCursor, // index of the last message read.
+    next: u64,         // index of the next message to read.
+}
+
+impl Subscriber {
+    /// Opens the broadcast queue at `path` for the subscriber called `name`. A subscriber
+    /// without a cursor yet, or with a cursor past the end of a recreated queue, starts at the
+    /// oldest message left in the ring.
+    pub fn open<P: AsRef<Path>>(path: P, name: &str) -> Result<Self> {
+        let queue = MmapQueue::open(path.as_ref())?;
+        if queue.mode() != QueueMode::BROADCAST {
+            bail!("{:?} is not a broadcast queue", path.as_ref());
+        }
+        let cursor = SeqCursor::open(path.as_ref().with_extension(format!("{name}.cursor")))?;
+
+        let tail = queue.header().tail.load(Ordering::Acquire);
+        let next = match cursor.load() {
+            Some(last) if last < tail as u128 => last as u64 + 1,
+            _ => queue.oldest(tail),
+        };
+        Ok(Self {
+            queue,
+            cursor,
+            next,
+        })
+    }
+
+    /// Number of messages published and not read by this subscriber yet.
+    pub fn lag(&self) -> u64 {
+        let tail = self.queue.header().tail.load(Ordering::Acquire);
+        tail.saturating_sub(self.next)
+    }
+
+    /// Reads the next message, or returns `None` when the subscriber is up to date. Returns a
+    /// `Lapped` error when the message was overwritten before it was read.
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let tail = self.queue.header().tail.load(Ordering::Acquire);
+        if self.next >= tail {
+            return Ok(None);
+        }
+
+        let index = self.next;
+        let expected = index.wrapping_add(1);
+        let sequence = self.queue.sequence(index);
+        let out = if sequence.load(Ordering::Acquire) == expected {
+            let out = self.queue.read_slot(index);
+            // the producer may have overwritten the slot while it was copied.
+            fence(Ordering::Acquire);
+            out.ok()
+                .filter(|_| sequence.load(Ordering::Relaxed) == expected)
+        } else {
+            None
+        };
+
+        match out {
+            Some(out) => {
+                self.next = expected;
+                self.cursor.store(index as u128);
+                Ok(Some(out))
+            }
+            None => {
+                let tail = self.queue.header().tail.load(Ordering::Acquire);
+                // the slot being overwritten is about to be the oldest one gone.
+                let oldest = self.queue.oldest(tail).max(index + 1);
+                self.next = oldest;
+                self.cursor.store(oldest as u128 - 1);
+                Err(Lapped {
+                    expected: index,
+                    oldest,
+                }
+                .into())
+            }
+        }
+    }
+}
+
+impl Consumer for Subscriber {
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        self.recv()
+    }
+
+    fn queue(&self) -> &MmapQueue {
+        &self.queue
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{bail, Context, Ok, Result};
+use memmap2::{MmapMut, MmapOptions};
+
+const MAGIC: u64 = 0x5253525543514553; // magic number
+
+/// Layout in the mmap file:
+/// [ magic u64 ][ slot0 ][ slot1 ]
+///
+/// Layout of a slot:
+/// [ version u64 ][ low u64 ][ high u64 ]
+///
+/// The stores alternate between the two slots, each store takes the next version and
+/// goes to the slot the previous store did not use. The version of a slot is odd while the
+/// sequence id is written, readers take the slot of the highest even version which is the
+/// same before and after reading it. A writer which dies in the middle of a store leaves
+/// its slot odd, readers then fall back to the value of the previous store in the other slot.
+/// A version of zero means nothing was stored in the slot.
+#[repr(C)]
+struct Slot {
+    version: AtomicU64,
+    low: AtomicU64,
+    high: AtomicU64,
+}
+
+impl Slot {
+    /// Reads the version and the sequence id of the slot, `None` when a writer changed the
+    /// slot while it was read. The inner value is `None` when nothing was stored in the slot
+    /// or its store is in progress or was torn.
+    fn read(&self) -> Option<Option<(u64, u128)>> {
+        let version = self.version.load(Ordering::Acquire);
+        let low = self.low.load(Ordering::Acquire);
+        let high = self.high.load(Ordering::Acquire);
+        if self.version.load(Ordering::Acquire) != version {
+            return None;
+        }
+        if version == 0 || !version.is_multiple_of(2) {
+            return Some(None);
+        }
+        Some(Some((version, ((high as u128) << 64) | low as u128)))
+    }
+}
+
+#[repr(C)]
+struct Layout {
+    magic: u64,
+    slots: [Slot; 2],
+}
+
+/// A sequence id shared between processes through a memory mapped file, a consumer stores
+/// the last sequence id it processed so a producer knows how far it got after a restart.
+pub struct SeqCursor {
+    pub file: File,
+    mmap: MmapMut,
+}
+
+unsafe impl Send for SeqCursor {}
+
+impl SeqCursor {
+    /// Opens the cursor at `path`, creating it when it does not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.as_ref())
+            .with_context(|| format!("open cursor {:?}", path.as_ref()))?;
+        let size = std::mem::size_of::<Layout>();
+        let len = file.metadata()?.len();
+        let created = len == 0;
+        if created {
+            file.set_len(size as u64)?;
+        } else if len != size as u64 {
+            bail!("file size doesn't match the layout of a cursor")
+        }
+
+        let mut mmap = unsafe { MmapOptions::new().len(size).map_mut(&file)? };
+        let layout = unsafe { &mut *(mmap.as_mut_ptr() as *mut Layout) };
+        if created {
+            layout.magic = MAGIC;
+        } else if layout.magic != MAGIC {
+            bail!("magic mismatch; file is not a cursor or corrupted")
+        }
+
+        Ok(Self { file, mmap })
+    }
+
+    #[inline]
+    fn slots(&self) -> &[Slot; 2] {
+        unsafe { &(*(self.mmap.as_ptr() as *const Layout)).slots }
+    }
+
+    /// The last stored sequence id, `None` when nothing was stored yet. It only retries while
+    /// a live writer changes the slots under it, a slot left odd by a dead writer is skipped.
+    pub fn load(&self) -> Option<u128> {
+        loop {
+            if let (Some(first), Some(second)) = (self.slots()[0].read(), self.slots()[1].read()) {
+                return first.max(second).map(|(_, seq_id)| seq_id);
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    pub fn store(&mut self, seq_id: u128) {
+        let slots = self.slots();
+        // the next version after the last store, even when the last writer died in its middle.
+        let last = slots
+            .iter()
+            .map(|slot| slot.version.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or_default();
+        let version = last.next_multiple_of(2);
+        let slot = &slots[(version / 2 % 2) as usize];
+        slot.version.store(version + 1, Ordering::Release);
+        slot.low.store(seq_id as u64, Ordering::Release);
+        slot.high.store((seq_id >> 64) as u64, Ordering::Release);
+        slot.version.store(version + 2, Ordering::Release);
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    ops::{Deref, DerefMut},
+    ptr, slice,
+    sync::atomic::{fence, Ordering},
+};
+
+use anyhow::{bail, Ok, Result};
+
+use crate::{MmapQueue, QueueMode};
+
+// ---------- ZERO-COPY ACCESS ----------
+
+impl MmapQueue {
+    /// Offset of the payload of the slot of the given index.
+    #[inline]
+    fn payload_offset(&self, index: u64) -> usize {
+        self.slot_offset(index) + self.mode.slot_header()
+    }
+
+    /// Writes the length prefix of the slot of the given index, `len` was checked by the caller.
+    fn set_len(&mut self, index: u64, len: usize) {
+        let offset = self.payload_offset(index) - 4;
+        unsafe { ptr::write_unaligned(self.mmap.as_mut_ptr().add(offset) as *mut u32, len as u32) }
+    }
+
+    /// Reads the length prefix of the slot of the given index.
+    fn get_len(&self, index: u64) -> Result<usize> {
+        let offset = self.payload_offset(index) - 4;
+        let len = unsafe { ptr::read_unaligned(self.mmap.as_ptr().add(offset) as *const u32) };
+        if len as usize > self.max_payload_size() {
+            bail!("corrupted length in slot");
+        }
+        Ok(len as usize)
+    }
+
+    fn payload(&self, index: u64, len: usize) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.mmap.as_ptr().add(self.payload_offset(index)), len) }
+    }
+
+    fn payload_mut(&mut self, index: u64, len: usize) -> &mut [u8] {
+        let offset = self.payload_offset(index);
+        unsafe { slice::from_raw_parts_mut(self.mmap.as_mut_ptr().add(offset), len) }
+    }
+
+    /// Claims the space of a payload of `len` bytes at the `tail` index for the producer,
+    /// returns where the payload starts and the tail once it is published. Returns an error
+    /// when the space still holds messages the consumer did not read.
+    fn claim(&mut self, tail: u64, len: usize) -> Result<(u64, u64)> {
+        if len > self.max_payload_size() {
+            bail!(
+                "payload is too large for slot (max {})",
+                self.max_payload_size()
+            )
+        }
+        let (start, next) = self.locate(tail, len);
+        match self.mode {
+            QueueMode::SPSC => {
+                let head = self.header().head.load(Ordering::Acquire);
+                if next.wrapping_sub(head) as usize > self.capacity {
+                    bail!("queue is overflowed")
+                }
+                if start != tail {
+                    self.mark_wrap(tail);
+                }
+            }
+            // the slot still holds the previous message until the payload is written.
+            QueueMode::BROADCAST => {}
+            QueueMode::MPSC => bail!("a multi-producer queue only takes whole payloads"),
+        }
+        Ok((start, next))
+    }
+
+    /// Prepares the slot of the given index for a payload of `len` bytes, called once before
+    /// the payload is first written. In BROADCAST mode the previous message of the slot is
+    /// overwritten from here on, so subscribers still reading it see they were lapped.
+    fn begin_write(&mut self, index: u64, len: usize) {
+        if self.mode == QueueMode::BROADCAST {
+            self.sequence(index).store(0, Ordering::Relaxed);
+            fence(Ordering::Release);
+        }
+        self.set_len(index, len);
+    }
+
+    /// Publishes everything claimed between the `tail` and the `next` tail index with a
+    /// single store.
+    fn publish(&mut self, tail: u64, next: u64) {
+        if self.mode == QueueMode::BROADCAST {
+            for index in tail..next {
+                self.sequence(index)
+                    .store(index.wrapping_add(1), Ordering::Release);
+            }
+        }
+        self.header().tail.store(next, Ordering::Release);
+        self.notify();
+    }
+
+    /// Hands everything read between the `head` and the `next` head index back to the
+    /// producers with a single store.
+    fn release(&mut self, head: u64, next: u64) {
+        if self.mode == QueueMode::MPSC {
+            for index in head..next {
+                self.sequence(index)
+                    .store(index.wrapping_add(self.capacity as u64), Ordering::Release);
+            }
+        }
+        self.header().head.store(next, Ordering::Release);
+    }
+
+    /// Reserves the next slot for a payload of `len` bytes which is written in place, the
+    /// message is only published by `WriteSlot::commit`.
+    /// ```rust
+    /// use memmap::MmapQueue;
+    ///
+    /// let path = std::env::temp_dir().join("reserve_doc.dat");
+    /// let mut queue = MmapQueue::create(&path, 4, 16).unwrap();
+    ///
+    /// let mut slot = queue.reserve(6).unwrap();
+    /// slot.copy_from_slice(b"ORDER1");
+    /// slot.commit();
+    ///
+    /// // an abandoned slot is never published.
+    /// queue.reserve(6).unwrap();
+    ///
+    /// assert_eq!(&*queue.peek().unwrap().unwrap(), b"ORDER1");
+    /// assert!(queue.peek().unwrap().is_none());
+    /// # let _ = std::fs::remove_file(&path);
+    /// ```
+    pub fn reserve(&mut self, len: usize) -> Result<WriteSlot<'_>> {
+        let tail = self.header().tail.load(Ordering::Relaxed);
+        let (start, next) = self.claim(tail, len)?;
+        Ok(WriteSlot {
+            queue: self,
+            tail,
+            start,
+            next,
+            len,
+            written: false,
+        })
+    }
+
+    /// Starts a batch of messages written in place and published together.
+    pub fn reserve_batch(&mut self) -> WriteBatch<'_> {
+        let tail = self.header().tail.load(Ordering::Relaxed);
+        WriteBatch {
+            queue: self,
+            tail,
+            next: tail,
+            count: 0,
+        }
+    }
+
+    /// Borrows the next message in its slot, the slot is handed back to the producer once
+    /// the `ReadSlot` is dropped.
+    pub fn peek(&mut self) -> Result<Option<ReadSlot<'_>>> {
+        let batch = self.peek_batch(1)?;
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        let (start, _) = batch.queue.next_message(batch.head);
+        let len = batch.queue.get_len(start)?;
+        Ok(Some(ReadSlot { batch, start, len }))
+    }
+
+    /// Borrows up to `max` messages in their slots, the slots are handed back to the producer
+    /// together once the `ReadBatch` is dropped.
+    pub fn peek_batch(&mut self, max: usize) -> Result<ReadBatch<'_>> {
+        if self.mode == QueueMode::BROADCAST {
+            bail!("a broadcast queue is read through a subscriber")
+        }
+        let head = self.header().head.load(Ordering::Acquire);
+        let tail = self.header().tail.load(Ordering::Acquire);
+
+        let mut count = 0;
+        let mut next = head;
+        while next != tail && count < max && self.is_committed(next) {
+            let (start, after) = self.next_message(next);
+            self.get_len(start)?;
+            next = after;
+            count += 1;
+        }
+        Ok(ReadBatch {
+            queue: self,
+            head,
+            next,
+            count,
+        })
+    }
+}
+
+/// A slot reserved by the producer, the payload is written through `DerefMut`. In a
+/// broadcast queue the previous message of the slot stays readable until then, a slot
+/// abandoned before its payload was written leaves it untouched.
+pub struct WriteSlot<'a> {
+    queue: &'a mut MmapQueue,
+    tail: u64,  // the tail index when the slot was reserved.
+    start: u64, // index the payload starts at.
+    next: u64,  // the tail index once the slot is published.
+    len: usize,
+    written: bool, // the slot was prepared for the payload by `begin_write`.
+}
+
+impl WriteSlot<'_> {
+    /// Publishes the message, dropping the slot instead abandons it.
+    pub fn commit(self) {
+        if !self.written {
+            self.queue.begin_write(self.start, self.len);
+        }
+        self.queue.publish(self.tail, self.next);
+    }
+}
+
+impl Deref for WriteSlot<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.queue.payload(self.start, self.len)
+    }
+}
+
+impl DerefMut for WriteSlot<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if !self.written {
+            self.queue.begin_write(self.start, self.len);
+            self.written = true;
+        }
+        self.queue.payload_mut(self.start, self.len)
+    }
+}
+
+/// Slots reserved one after the other by the producer and published with a single store of
+/// the tail, dropping the batch without `commit` abandons all of them.
+pub struct WriteBatch<'a> {
+    queue: &'a mut MmapQueue,
+    tail: u64,  // index of the first slot of the batch.
+    next: u64,  // the tail index once the batch is published.
+    count: u64, // slots reserved so far.
+}
+
+impl WriteBatch<'_> {
+    /// Reserves the next slot of the batch for a payload of `len` bytes, returns an error
+    /// when the queue has no free slot left.
+    pub fn push(&mut self, len: usize) -> Result<&mut [u8]> {
+        if self.count as usize >= self.queue.capacity {
+            bail!("batch is larger than the queue")
+        }
+        let (start, next) = self.queue.claim(self.next, len)?;
+        self.queue.begin_write(start, len);
+        self.next = next;
+        self.count += 1;
+        Ok(self.queue.payload_mut(start, len))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Publishes every message of the batch at once.
+    pub fn commit(self) {
+        if self.count > 0 {
+            self.queue.publish(self.tail, self.next);
+        }
+    }
+}
+
+/// A message borrowed in its slot, the slot is handed back to the producer on drop.
+pub struct ReadSlot<'a> {
+    batch: ReadBatch<'a>, // a batch of a single slot.
+    start: u64,           // index the payload starts at.
+    len: usize,
+}
+
+impl Deref for ReadSlot<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.batch.queue.payload(self.start, self.len)
+    }
+}
+
+/// Messages borrowed in their slots, the slots are handed back to the producer with a
+/// single store of the head on drop.
+/// ```rust
+/// use memmap::MmapQueue;
+///
+/// let path = std::env::temp_dir().join("peek_batch_doc.dat");
+/// let mut queue = MmapQueue::create(&path, 4, 16).unwrap();
+/// let mut batch = queue.reserve_batch();
+/// for order in [b"ORDER1", b"ORDER2", b"ORDER3"] {
+///     batch.push(order.len()).unwrap().copy_from_slice(order);
+/// }
+/// batch.commit();
+///
+/// let batch = queue.peek_batch(2).unwrap();
+/// assert_eq!(batch.iter().collect::<Vec<_>>(), vec![b"ORDER1", b"ORDER2"]);
+/// drop(batch);
+/// assert_eq!(queue.dequeue().unwrap(), Some(b"ORDER3".to_vec()));
+/// # let _ = std::fs::remove_file(&path);
+/// ```
+pub struct ReadBatch<'a> {
+    queue: &'a mut MmapQueue,
+    head: u64,    // index of the first slot of the batch.
+    next: u64,    // the head index once the batch is handed back.
+    count: usize, // slots in the batch, their lengths were checked when it was taken.
+}
+
+impl ReadBatch<'_> {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The payload of the message at position `i` of the batch.
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        self.iter().nth(i)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.count)
+            .scan(self.head, |index, _| {
+                let (start, next) = self.queue.next_message(*index);
+                *index = next;
+                Some(start)
+            })
+            .filter_map(|start| {
+                let len = self.queue.get_len(start).ok()?;
+                Some(self.queue.payload(start, len))
+            })
+    }
+}
+
+impl Drop for ReadBatch<'_> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.queue.release(self.head, self.next);
+        }
+    }
+}
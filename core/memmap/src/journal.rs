@@ -0,0 +1,373 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Ok, Result};
+
+const MAGIC: u64 = 0x4A4E524C57414C31; // magic number at the start of every segment.
+const SEGMENT_HEADER_SIZE: usize = 8;
+const RECORD_HEADER_SIZE: usize = 4 + 4 + 16; // length, crc and sequence id.
+
+/// When the journal forces the appended records to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    ALWAYS,             // after every record, nothing acknowledged is ever lost.
+    BATCH(usize),       // once the given number of records were appended since the last sync.
+    INTERVAL(Duration), // on an append or `sync_if_due` once the given time passed since the last sync.
+}
+
+/// Layout of a segment file:
+/// [ magic u64 ][ record0 ][ record1 ]...
+///
+/// Layout of a record:
+/// [ payload length u32 ][ crc32 u32 ][ seq_id u128 ][ payload ]
+///
+/// The crc covers the sequence id and the payload. Segments are named after the sequence id
+/// of their first record, so they sort in sequence order.
+fn segment_path(dir: &Path, first_seq_id: u128) -> PathBuf {
+    dir.join(format!("{:032x}.wal", first_seq_id))
+}
+
+/// Returns the segments of the journal sorted by the sequence id of their first record.
+fn segments(dir: &Path) -> Result<Vec<(u128, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("read journal {:?}", dir))? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "wal") {
+            continue;
+        }
+        let first_seq_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| u128::from_str_radix(stem, 16).ok());
+        if let Some(first_seq_id) = first_seq_id {
+            segments.push((first_seq_id, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn crc(seq_id: u128, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&seq_id.to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// Decodes the record at the start of `buf`, returns `None` when `buf` holds no complete
+/// record and an error when the record does not match its crc.
+fn decode(buf: &[u8]) -> Result<Option<(u128, &[u8])>> {
+    if buf.len() < RECORD_HEADER_SIZE {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(buf[0..4].try_into()?) as usize;
+    let checksum = u32::from_le_bytes(buf[4..8].try_into()?);
+    let seq_id = u128::from_le_bytes(buf[8..24].try_into()?);
+    let payload = match buf.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len) {
+        Some(payload) => payload,
+        None => return Ok(None),
+    };
+    if crc(seq_id, payload) != checksum {
+        bail!("crc mismatch in record {}", seq_id);
+    }
+    Ok(Some((seq_id, payload)))
+}
+
+/// Returns the size of the record at the start of `buf` as written in its header, `None`
+/// when not even the header is there.
+fn record_end(buf: &[u8]) -> Option<usize> {
+    let len = u32::from_le_bytes(buf.get(0..4)?.try_into().ok()?) as usize;
+    Some(RECORD_HEADER_SIZE + len)
+}
+
+/// Reads a whole segment and checks its header.
+fn read_segment(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)
+        .with_context(|| format!("open segment {:?}", path))?
+        .read_to_end(&mut buf)?;
+    if buf.len() < SEGMENT_HEADER_SIZE || u64::from_le_bytes(buf[..8].try_into()?) != MAGIC {
+        bail!("magic mismatch; {:?} is not a journal segment", path);
+    }
+    Ok(buf)
+}
+
+/// Syncs the entries of the journal directory, so a created or removed segment survives a crash.
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("sync journal {:?}", dir))
+}
+
+/// An append-only write-ahead log of sequenced records, split in segment files of at most
+/// `segment_size` bytes. Records are appended in increasing sequence id order and survive
+/// restarts, a record torn by a crash at the end of the last segment is cut off on open and
+/// any other damaged record fails the open.
+pub struct Journal {
+    dir: PathBuf,
+    segment_size: u64,
+    sync: SyncPolicy,
+    file: Option<File>, // the last segment, `None` until the first record is appended.
+    written: u64,       // bytes in the current segment.
+    last_seq_id: Option<u128>, // sequence id of the last appended record.
+    unsynced: usize,    // records appended since the last sync.
+    torn: u64,          // bytes of a torn record cut off the last segment on open.
+    last_sync: Instant,
+}
+
+impl Journal {
+    /// Opens the journal in `dir` for appending, creating the directory if needed.
+    pub fn open<P: AsRef<Path>>(dir: P, segment_size: u64, sync: SyncPolicy) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).with_context(|| format!("create journal {:?}", dir))?;
+
+        // a crash while a segment was created can leave it shorter than its header, it holds
+        // no record yet so it is removed and the journal carries on in the segment before it.
+        let mut segments = segments(&dir)?;
+        let mut blank = 0;
+        while let Some((_, path)) = segments.last() {
+            let len = fs::metadata(path)?.len();
+            if len >= SEGMENT_HEADER_SIZE as u64 {
+                break;
+            }
+            fs::remove_file(path).with_context(|| format!("remove segment {:?}", path))?;
+            sync_dir(&dir)?;
+            blank += len;
+            segments.pop();
+        }
+
+        let (file, written, last_seq_id, torn) = match segments.pop() {
+            Some((_, path)) => {
+                let buf = read_segment(&path)?;
+                // keep every complete record, only the last record of the newest segment can
+                // have been torn by a crash, a bad record before it is a corrupted journal.
+                let mut offset = SEGMENT_HEADER_SIZE;
+                let mut last_seq_id = None;
+                loop {
+                    match decode(&buf[offset..]) {
+                        Result::Ok(Some((seq_id, payload))) => {
+                            last_seq_id = Some(seq_id);
+                            offset += RECORD_HEADER_SIZE + payload.len();
+                        }
+                        Result::Ok(None) => break,
+                        Err(_) if record_end(&buf[offset..]) == Some(buf.len() - offset) => break,
+                        Err(err) => {
+                            return Err(err.context(format!("corrupted segment {:?}", path)))
+                        }
+                    }
+                }
+                let file = OpenOptions::new().append(true).open(&path)?;
+                file.set_len(offset as u64)?;
+                let torn = (buf.len() - offset) as u64;
+                (Some(file), offset as u64, last_seq_id, torn + blank)
+            }
+            None => (None, 0, None, blank),
+        };
+
+        Ok(Self {
+            dir,
+            segment_size,
+            sync,
+            file,
+            written,
+            last_seq_id,
+            unsynced: 0,
+            torn,
+            last_sync: Instant::now(),
+        })
+    }
+
+    fn create_segment(dir: &Path, first_seq_id: u128) -> Result<File> {
+        let path = segment_path(dir, first_seq_id);
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("create segment {:?}", path))?;
+        file.write_all(&MAGIC.to_le_bytes())?;
+        file.sync_all()?;
+        // the entry of the new segment only survives a crash once its directory is synced.
+        sync_dir(dir)?;
+        Ok(file)
+    }
+
+    /// Sequence id of the last record of the journal.
+    pub fn last_seq_id(&self) -> Option<u128> {
+        self.last_seq_id
+    }
+
+    /// Number of bytes cut off the end of the journal when it was opened, anything but zero
+    /// means the last record was torn by a crash and never made it to the disk.
+    pub fn torn_bytes(&self) -> u64 {
+        self.torn
+    }
+
+    /// Appends a record, sequence ids must be strictly increasing. The record is synced to
+    /// the disk according to the sync policy of the journal.
+    pub fn append(&mut self, seq_id: u128, payload: &[u8]) -> Result<()> {
+        if self.last_seq_id.is_some_and(|last| seq_id <= last) {
+            bail!(
+                "record {} is not after the last record of the journal",
+                seq_id
+            );
+        }
+
+        let len = (RECORD_HEADER_SIZE + payload.len()) as u64;
+        // an empty segment takes any record, however large it is.
+        let full =
+            self.written + len > self.segment_size && self.written > SEGMENT_HEADER_SIZE as u64;
+        let file = match self.file.take() {
+            Some(file) if !full => file,
+            previous => {
+                if let Some(previous) = previous {
+                    previous.sync_data()?;
+                }
+                self.unsynced = 0;
+                self.written = SEGMENT_HEADER_SIZE as u64;
+                Self::create_segment(&self.dir, seq_id)?
+            }
+        };
+        let file = self.file.insert(file);
+
+        let mut record = Vec::with_capacity(len as usize);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc(seq_id, payload).to_le_bytes());
+        record.extend_from_slice(&seq_id.to_le_bytes());
+        record.extend_from_slice(payload);
+        file.write_all(&record)?;
+        self.written += len;
+        self.last_seq_id = Some(seq_id);
+        self.unsynced += 1;
+
+        if self.is_due() {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the sync policy asks for the appended records to be synced now.
+    fn is_due(&self) -> bool {
+        match self.sync {
+            SyncPolicy::ALWAYS => true,
+            SyncPolicy::BATCH(records) => self.unsynced >= records,
+            SyncPolicy::INTERVAL(interval) => self.last_sync.elapsed() >= interval,
+        }
+    }
+
+    /// Syncs the records appended since the last sync once the `INTERVAL` of the policy
+    /// elapsed. A writer calls it while it is idle, so the last records of a journal which
+    /// stopped receiving records are not left unsynced until the next append.
+    pub fn sync_if_due(&mut self) -> Result<()> {
+        if self.unsynced > 0 && self.is_due() {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Forces every appended record to the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_ref().filter(|_| self.unsynced > 0) {
+            file.sync_data()?;
+            self.unsynced = 0;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+/// Iterates over the records of a journal in sequence order, starting at a given sequence id.
+/// ```rust
+/// use memmap::{Journal, JournalReader, SyncPolicy};
+///
+/// let dir = std::env::temp_dir().join("journal_doc");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS).unwrap();
+/// for seq_id in 0..5u128 {
+///     journal.append(seq_id, format!("ORDER{seq_id}").as_bytes()).unwrap();
+/// }
+///
+/// let records: Vec<_> = JournalReader::open(&dir, 3).unwrap().map(|r| r.unwrap()).collect();
+/// assert_eq!(records, vec![(3, b"ORDER3".to_vec()), (4, b"ORDER4".to_vec())]);
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// ```
+pub struct JournalReader {
+    segments: Vec<PathBuf>, // segments left to read, the next one last.
+    buf: Vec<u8>,           // the segment being read.
+    offset: usize,
+    from_seq_id: u128,
+}
+
+impl JournalReader {
+    pub fn open<P: AsRef<Path>>(dir: P, from_seq_id: u128) -> Result<Self> {
+        let segments = segments(dir.as_ref())?;
+        // the records before `from_seq_id` all live in the segments before the last one
+        // starting at or before it.
+        let start = segments
+            .iter()
+            .rposition(|(first_seq_id, _)| *first_seq_id <= from_seq_id)
+            .unwrap_or(0);
+        let mut segments: Vec<PathBuf> = segments
+            .into_iter()
+            .skip(start)
+            .map(|(_, path)| path)
+            .collect();
+        segments.reverse();
+
+        Ok(Self {
+            segments,
+            buf: Vec::new(),
+            offset: 0,
+            from_seq_id,
+        })
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = Result<(u128, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match decode(self.buf.get(self.offset..).unwrap_or_default()) {
+                Result::Ok(Some((seq_id, payload))) => {
+                    self.offset += RECORD_HEADER_SIZE + payload.len();
+                    if seq_id >= self.from_seq_id {
+                        return Some(Ok((seq_id, payload.to_vec())));
+                    }
+                }
+                // the end of a segment, or a record which is still being written.
+                Result::Ok(None) => {
+                    let path = self.segments.pop()?;
+                    match read_segment(&path) {
+                        Result::Ok(buf) => self.buf = buf,
+                        // the newest segment is still being created, it holds no record yet.
+                        Err(_)
+                            if self.segments.is_empty()
+                                && fs::metadata(&path)
+                                    .is_ok_and(|m| m.len() < SEGMENT_HEADER_SIZE as u64) =>
+                        {
+                            return None
+                        }
+                        Err(err) => return Some(Err(err)),
+                    }
+                    self.offset = SEGMENT_HEADER_SIZE;
+                }
+                Err(err) => {
+                    // a corrupted record ends the iteration.
+                    self.segments.clear();
+                    self.buf.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
@@ -2,25 +2,91 @@ use std::{
     fs::{File, OpenOptions},
     path::Path,
     ptr,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Ok, Result};
 use memmap2::{MmapMut, MmapOptions};
 
+pub mod broadcast;
+pub mod cursor;
+pub mod guard;
+pub mod journal;
+pub mod wait;
+
+pub use broadcast::{Lapped, Subscriber};
+pub use cursor::SeqCursor;
+pub use guard::{ReadBatch, ReadSlot, WriteBatch, WriteSlot};
+pub use journal::{Journal, JournalReader, SyncPolicy};
+pub use wait::{AsyncReceiver, Backoff, Consumer, WaitStrategy};
+
 const MAGIC: u64 = 0x4D514D50524F4451; // magic number
+const VERSION: u64 = 2; // version of the file layout, bumped whenever `Header` or the slots change.
+const WRAP: u32 = u32::MAX; // length of the marker which sends the consumer back to the start of a ring of records.
+
+/// How many processes may write to the queue, chosen when the queue is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueMode {
+    SPSC, // a single producer, the tail is a plain store.
+    MPSC, // many producers, each one claims its slot by a CAS on the tail.
+    BROADCAST, // a single producer which never waits, every `Subscriber` reads every message.
+}
+
+impl QueueMode {
+    /// Bytes in front of the payload of every slot: the commit sequence in MPSC mode, then
+    /// the u32 length prefix.
+    fn slot_header(&self) -> usize {
+        match self {
+            QueueMode::SPSC => 4,
+            QueueMode::MPSC | QueueMode::BROADCAST => 8 + 4,
+        }
+    }
 
+    /// Bytes taken by a slot which holds payloads of up to `payload_size` bytes.
+    fn slot_size(&self, payload_size: usize) -> usize {
+        match self {
+            QueueMode::SPSC => self.slot_header() + payload_size,
+            // the commit sequence of every slot must be aligned for atomic access.
+            QueueMode::MPSC | QueueMode::BROADCAST => {
+                (self.slot_header() + payload_size).next_multiple_of(8)
+            }
+        }
+    }
+}
 
 /// Layout in the mmap file:
 /// [ Header (aligned) ] [ slot0 ][ slot1 ]...[ slotN-1 ]
+///
+/// Layout of a slot:
+/// SPSC: [ len u32 ][ payload ]
+/// MPSC and BROADCAST: [ sequence u64 ][ len u32 ][ payload ]
+///
+/// In MPSC mode the sequence of slot `i` starts at `i`, a producer that claimed the tail
+/// index `t` stores `t + 1` once the payload is written and the consumer stores `t + capacity`
+/// once it is read, so the consumer never reads a slot which is still being written.
+///
+/// In BROADCAST mode the head is unused and the producer overwrites the oldest slot once the
+/// ring is full. The sequence of a slot is zero while it is written and `t + 1` once it holds
+/// the message of index `t`, a subscriber which finds another sequence was lapped.
+///
+/// A ring of records (SPSC only) is a ring of `capacity` bytes, `head` and `tail` count bytes
+/// and every record takes only the space it needs, rounded up to 8 bytes:
+/// [ len u32 ][ payload ][ padding ]
+/// A record never wraps around the end of the ring, when it doesn't fit before the end the
+/// producer writes a `WRAP` length there and the record goes at the start of the ring.
 #[repr(C)]
 pub struct Header {
     magic: u64,
+    version: u64, // layout the file was created with, only files of `VERSION` can be opened.
     capacity: u64,  // size of the queue
     slot_size: u64, // size of element within the queue
     head: AtomicU64, // front index
     tail: AtomicU64, // back index
     mask: u64, // mask for getting the correct index
+    mode: u64, // 0 for SPSC, 1 for MPSC and 2 for BROADCAST.
+    waiters: AtomicU64, // consumers parked on the tail, the producer only wakes them when there are some.
+    max_record: u64, // 0 for fixed slots, otherwise the largest payload of a record in a ring of records.
 }
 
 impl Header {
@@ -29,7 +95,8 @@ impl Header {
     }
 }
 
-/// A memory-mapped mmapped single-producer/single-consumer queue.
+/// A memory-mapped mmapped queue with a single consumer and either a single producer or
+/// many producers, or a broadcast ring read by many subscribers, see `QueueMode`.
 pub struct MmapQueue {
     pub file: File,
     mmap: MmapMut,
@@ -38,25 +105,108 @@ pub struct MmapQueue {
     capacity: usize,
     slot_size: usize,
     mask: usize,
+    mode: QueueMode,
+    max_payload: usize, // the largest payload of a message.
+    records: bool,      // the ring holds records of any length instead of fixed slots.
+}
+
+/// Bytes taken in a ring of records by a record with a payload of `len` bytes.
+#[inline]
+fn record_size(len: usize) -> usize {
+    (4 + len).next_multiple_of(8)
 }
 
 unsafe impl Send for MmapQueue {}
 unsafe impl Sync for MmapQueue {}
 
 impl MmapQueue {
-    /// Create and initialize a new queue file at `path`.
+    /// Create and initialize a new single-producer queue file at `path`.
     /// capacity must be a power of two.
     /// slot_payload_size is the max payload size (u32 length prefix is added automatically).
     pub fn create<P: AsRef<Path>>(
         path: P,
         capacity: usize,
         slot_payload_size: usize,
+    ) -> Result<Self> {
+        Self::create_with_mode(path, capacity, slot_payload_size, QueueMode::SPSC)
+    }
+
+    /// Create and initialize a new queue file at `path` which accepts the producers of the
+    /// given mode, the mode is recorded in the header so every process opening the queue uses it.
+    pub fn create_with_mode<P: AsRef<Path>>(
+        path: P,
+        capacity: usize,
+        slot_payload_size: usize,
+        mode: QueueMode,
     ) -> Result<Self> {
         if !capacity.is_power_of_two() {
             bail!("capacity must be power of two");
         }
 
-        let slot_size = 4usize + slot_payload_size;
+        Self::init(path, capacity, mode.slot_size(slot_payload_size), mode, None)
+    }
+
+    /// Opens the queue at `path` with the messages it still holds, the queue is created
+    /// instead when there is none or when it was created with another mode or size.
+    pub fn open_or_create_with_mode<P: AsRef<Path>>(
+        path: P,
+        capacity: usize,
+        slot_payload_size: usize,
+        mode: QueueMode,
+    ) -> Result<Self> {
+        if let Result::Ok(queue) = Self::open(path.as_ref()) {
+            if !queue.records
+                && queue.mode == mode
+                && queue.capacity == capacity
+                && queue.slot_size == mode.slot_size(slot_payload_size)
+            {
+                return Ok(queue);
+            }
+        }
+        Self::create_with_mode(path, capacity, slot_payload_size, mode)
+    }
+
+    /// Create and initialize a new single-producer ring of records at `path`. The ring holds
+    /// `ring_size` bytes, which must be a power of two, and takes records of up to `max_record`
+    /// bytes, each one taking only the space it needs.
+    pub fn create_records<P: AsRef<Path>>(
+        path: P,
+        ring_size: usize,
+        max_record: usize,
+    ) -> Result<Self> {
+        if !ring_size.is_power_of_two() {
+            bail!("ring size must be power of two");
+        }
+        // a record which doesn't fit before the end of the ring wastes the space left there.
+        if ring_size < 2 * record_size(max_record) {
+            bail!("ring size must hold at least two records of the maximum size");
+        }
+        Self::init(path, ring_size, 1, QueueMode::SPSC, Some(max_record))
+    }
+
+    /// Opens the ring of records at `path` with the messages it still holds, the ring is
+    /// created instead when there is none or when it was created with another size.
+    pub fn open_or_create_records<P: AsRef<Path>>(
+        path: P,
+        ring_size: usize,
+        max_record: usize,
+    ) -> Result<Self> {
+        if let Result::Ok(queue) = Self::open(path.as_ref()) {
+            if queue.records && queue.capacity == ring_size && queue.max_payload == max_record {
+                return Ok(queue);
+            }
+        }
+        Self::create_records(path, ring_size, max_record)
+    }
+
+    /// Creates the queue file, a ring of records is a queue of 1 byte slots.
+    fn init<P: AsRef<Path>>(
+        path: P,
+        capacity: usize,
+        slot_size: usize,
+        mode: QueueMode,
+        max_record: Option<usize>,
+    ) -> Result<Self> {
         let header_size = Header::size();
         let total_size = header_size + capacity * slot_size;
 
@@ -79,15 +229,23 @@ impl MmapQueue {
 
             let hdr = &mut *header_ptr;
             hdr.magic = MAGIC;
+            hdr.version = VERSION;
             hdr.capacity = capacity as u64;
             hdr.slot_size = slot_size as u64;
             hdr.mask = (capacity - 1) as u64;
+            hdr.mode = match mode {
+                QueueMode::SPSC => 0,
+                QueueMode::MPSC => 1,
+                QueueMode::BROADCAST => 2,
+            };
             // AtomicU64 fields default to zero (head/tail)
             // ensure head/tail are zeroes already
             hdr.head.store(0, Ordering::Relaxed);
             hdr.tail.store(0, Ordering::Relaxed);
+            hdr.waiters.store(0, Ordering::Relaxed);
+            hdr.max_record = max_record.unwrap_or_default() as u64;
         }
-        Ok(Self {
+        let queue = Self {
             file,
             mmap,
             header_ptr,
@@ -95,7 +253,16 @@ impl MmapQueue {
             capacity,
             slot_size,
             mask: capacity - 1,
-        })
+            mode,
+            max_payload: max_record.unwrap_or_else(|| slot_size - mode.slot_header()),
+            records: max_record.is_some(),
+        };
+        if mode == QueueMode::MPSC {
+            for index in 0..capacity as u64 {
+                queue.sequence(index).store(index, Ordering::Relaxed);
+            }
+        }
+        Ok(queue)
     }
 
     /// Open and get direct access to the memory mapped file
@@ -120,6 +287,13 @@ impl MmapQueue {
             if hdr.magic != MAGIC {
                 bail!("magic mismatch; file is not a valid queue or corrupted")
             }
+            if hdr.version != VERSION {
+                bail!(
+                    "version mismatch; file has layout {} but {} is expected",
+                    hdr.version,
+                    VERSION
+                )
+            }
 
             let capacity = hdr.capacity as usize;
             let slot_size = hdr.slot_size as usize;
@@ -132,6 +306,13 @@ impl MmapQueue {
                     total_size
                 );
             }
+            let mode = match hdr.mode {
+                0 => QueueMode::SPSC,
+                1 => QueueMode::MPSC,
+                2 => QueueMode::BROADCAST,
+                mode => bail!("unknown queue mode {}", mode),
+            };
+            let records = hdr.max_record > 0;
 
             Ok(Self {
                 file,
@@ -141,6 +322,12 @@ impl MmapQueue {
                 capacity,
                 slot_size,
                 mask: (hdr.mask as usize),
+                mode,
+                max_payload: match records {
+                    true => hdr.max_record as usize,
+                    false => slot_size - mode.slot_header(),
+                },
+                records,
             })
         }
     }
@@ -150,29 +337,154 @@ impl MmapQueue {
         unsafe { &*self.header_ptr }
     }
 
-    /// This method is one of the core logic of this crate, basically
-    /// does some validation about the memory mapped file and then just 
-    /// stores the data into the tail index and increments till it reaches the `capacity`
-    pub fn enqueue(&mut self, payload: &[u8]) -> Result<()> {
-        if payload.len() > self.slot_size - 4 {
-            bail!("payload is too large for slot (max {})", self.slot_size - 4)
+    pub fn mode(&self) -> QueueMode {
+        self.mode
+    }
+
+    /// Maps the same queue file a second time, so another thread gets its own handle.
+    fn try_clone(&self) -> Result<Self> {
+        let file = self.file.try_clone()?;
+        let mut mmap = unsafe { MmapOptions::new().len(self.mmap.len()).map_mut(&file)? };
+        let header_ptr = mmap.as_mut_ptr() as *mut Header;
+        Ok(Self {
+            file,
+            mmap,
+            header_ptr,
+            data_offset: self.data_offset,
+            capacity: self.capacity,
+            slot_size: self.slot_size,
+            mask: self.mask,
+            mode: self.mode,
+            max_payload: self.max_payload,
+            records: self.records,
+        })
+    }
+
+    /// The low half of the tail index, the word parked consumers sleep on.
+    #[inline]
+    fn tail_word(&self) -> &AtomicU32 {
+        let tail = &self.header().tail as *const AtomicU64 as *const AtomicU32;
+        unsafe { &*tail.add(if cfg!(target_endian = "big") { 1 } else { 0 }) }
+    }
+
+    /// Returns `true` when nothing was published since the consumer saw the given `tail`
+    /// index, and no producer is still writing a slot it claimed.
+    fn is_idle(&self, observed: u64) -> bool {
+        let tail = self.header().tail.load(Ordering::Acquire);
+        tail == observed
+            && (self.mode == QueueMode::BROADCAST
+                || self.header().head.load(Ordering::Acquire) == observed)
+    }
+
+    /// Puts the calling thread to sleep until a producer publishes after the given `tail`
+    /// index or the timeout elapses, it may also wake up spuriously.
+    fn park(&self, observed: u64, timeout: Option<Duration>) {
+        self.header().waiters.fetch_add(1, Ordering::SeqCst);
+        if self.is_idle(observed) {
+            wait::futex_wait(self.tail_word(), observed as u32, timeout);
         }
+        self.header().waiters.fetch_sub(1, Ordering::SeqCst);
+    }
 
-        // load indexes
+    /// Wakes the consumers parked on the tail, called by the producer after every publish.
+    #[inline]
+    fn notify(&self) {
+        fence(Ordering::SeqCst);
+        if self.header().waiters.load(Ordering::Relaxed) > 0 {
+            wait::futex_wake(self.tail_word());
+        }
+    }
+
+    #[inline]
+    fn slot_offset(&self, index: u64) -> usize {
+        self.data_offset + ((index as usize) & self.mask) * self.slot_size
+    }
+
+    /// The commit sequence of the slot of the given index, only used in MPSC mode.
+    #[inline]
+    fn sequence(&self, index: u64) -> &AtomicU64 {
+        unsafe { &*(self.mmap.as_ptr().add(self.slot_offset(index)) as *const AtomicU64) }
+    }
+
+    /// Number of messages published by the producer and not yet consumed, in BROADCAST mode
+    /// the number of messages still held by the ring and in a ring of records the number of
+    /// bytes they take.
+    pub fn len(&self) -> usize {
         let tail = self.header().tail.load(Ordering::Acquire);
-        let head = self.header().head.load(Ordering::Acquire);
+        let head = self.oldest(tail);
+        tail.wrapping_sub(head) as usize
+    }
+
+    /// Index of the oldest message still in the queue, given the current `tail` index.
+    fn oldest(&self, tail: u64) -> u64 {
+        match self.mode {
+            QueueMode::BROADCAST => tail.saturating_sub(self.capacity as u64),
+            _ => self.header().head.load(Ordering::Acquire),
+        }
+    }
 
-        let next_tail = tail.wrapping_add(1);
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        if next_tail.wrapping_sub(head) as usize > self.capacity {
-            bail!("queue is overflowed")
+    /// The largest payload a single slot can hold.
+    pub fn max_payload_size(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Returns `true` when the next `enqueue` would overflow the queue, a broadcast ring is
+    /// never full. A ring of records is full once a record of the maximum size may not fit.
+    pub fn is_full(&self) -> bool {
+        match self.records {
+            true => self.capacity - self.len() < 2 * record_size(self.max_payload),
+            false => self.mode != QueueMode::BROADCAST && self.len() >= self.capacity,
+        }
+    }
+
+    /// Returns where a payload of `len` bytes is written when the tail is at `tail`, and the
+    /// tail once it is published. A record which doesn't fit before the end of the ring goes
+    /// at its start.
+    fn locate(&self, tail: u64, len: usize) -> (u64, u64) {
+        if !self.records {
+            return (tail, tail.wrapping_add(1));
         }
+        let size = record_size(len) as u64;
+        let room = (self.capacity - (tail as usize & self.mask)) as u64;
+        let start = if size > room {
+            tail.wrapping_add(room)
+        } else {
+            tail
+        };
+        (start, start.wrapping_add(size))
+    }
+
+    /// Writes the marker which sends the consumer from `index` to the start of the ring.
+    fn mark_wrap(&mut self, index: u64) {
+        let offset = self.slot_offset(index);
+        unsafe { ptr::write_unaligned(self.mmap.as_mut_ptr().add(offset) as *mut u32, WRAP) }
+    }
 
-        let idx = (tail as usize) & self.mask;
-        let slot_offset = self.data_offset + idx * self.slot_size;
+    /// Returns where the message at the `head` index starts, and the head once it is read.
+    fn next_message(&self, head: u64) -> (u64, u64) {
+        if !self.records {
+            return (head, head.wrapping_add(1));
+        }
+        let len_ptr = unsafe { self.mmap.as_ptr().add(self.slot_offset(head)) as *const u32 };
+        let start = match unsafe { ptr::read_unaligned(len_ptr) } {
+            WRAP => head.wrapping_add((self.capacity - (head as usize & self.mask)) as u64),
+            _ => head,
+        };
+        let len_ptr = unsafe { self.mmap.as_ptr().add(self.slot_offset(start)) as *const u32 };
+        let len = unsafe { ptr::read_unaligned(len_ptr) } as usize;
+        // a corrupted length is caught when the payload is read.
+        (start, start.wrapping_add(record_size(len.min(self.max_payload)) as u64))
+    }
 
-        let len_ptr = unsafe { self.mmap.as_mut_ptr().add(slot_offset) as *mut u32 };
-        let buf_ptr = unsafe { self.mmap.as_mut_ptr().add(slot_offset + 4) };
+    /// Copies the payload into the slot of the given index, after its length prefix.
+    fn write_slot(&mut self, index: u64, payload: &[u8]) {
+        let len_offset = self.slot_offset(index) + self.mode.slot_header() - 4;
+        let len_ptr = unsafe { self.mmap.as_mut_ptr().add(len_offset) as *mut u32 };
+        let buf_ptr = unsafe { self.mmap.as_mut_ptr().add(len_offset + 4) };
 
         // write
         unsafe {
@@ -180,38 +492,114 @@ impl MmapQueue {
 
             ptr::copy_nonoverlapping(payload.as_ptr(), buf_ptr, payload.len());
 
-            if payload.len() < self.slot_size - 4 {
-                let extra = self.slot_size - 4 - payload.len();
+            if !self.records && payload.len() < self.max_payload_size() {
+                let extra = self.max_payload_size() - payload.len();
                 let rem_ptr = buf_ptr.add(payload.len());
                 ptr::write_bytes(rem_ptr, 0, extra);
             }
         }
+    }
+
+    /// This method is one of the core logic of this crate, basically
+    /// does some validation about the memory mapped file and then just 
+    /// stores the data into the tail index and increments till it reaches the `capacity`
+    pub fn enqueue(&mut self, payload: &[u8]) -> Result<()> {
+        if payload.len() > self.max_payload_size() {
+            bail!("payload is too large for slot (max {})", self.max_payload_size())
+        }
+        match self.mode {
+            QueueMode::MPSC => return self.enqueue_shared(payload),
+            QueueMode::BROADCAST => return self.enqueue_broadcast(payload),
+            QueueMode::SPSC => {}
+        }
+
+        // load indexes
+        let tail = self.header().tail.load(Ordering::Acquire);
+        let head = self.header().head.load(Ordering::Acquire);
+
+        let (start, next_tail) = self.locate(tail, payload.len());
+
+        if next_tail.wrapping_sub(head) as usize > self.capacity {
+            bail!("queue is overflowed")
+        }
+
+        if start != tail {
+            self.mark_wrap(tail);
+        }
+        self.write_slot(start, payload);
 
         // publish by incrementing tail (release)
         self.header().tail.store(next_tail, Ordering::Release);
+        self.notify();
 
         Ok(())
     }
 
-    /// This method is the second most important in this crate, it just takes the `head` index and 
-    /// tries to get the payload out of the current slot and increments the `head` index till it reaches 
-    /// the current `tail` index.
-    pub fn dequeue(&mut self) -> Result<Option<Vec<u8>>> {
-        let head = self.header().head.load(Ordering::Acquire);
-        let tail = self.header().tail.load(Ordering::Acquire);
-
-        if tail == head {
-            return Ok(None);
+    /// Enqueues a payload next to other producers, the tail index is claimed by a CAS and
+    /// the slot is committed by its sequence once the payload is written.
+    fn enqueue_shared(&mut self, payload: &[u8]) -> Result<()> {
+        let mut tail = self.header().tail.load(Ordering::Relaxed);
+        loop {
+            let sequence = self.sequence(tail).load(Ordering::Acquire);
+            match sequence.cmp(&tail) {
+                // the slot is free, try to claim it.
+                std::cmp::Ordering::Equal => match self.header().tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                ) {
+                    Result::Ok(_) => break,
+                    Err(current) => tail = current,
+                },
+                // the slot still holds the message of the previous lap.
+                std::cmp::Ordering::Less => bail!("queue is overflowed"),
+                // another producer claimed the slot first.
+                std::cmp::Ordering::Greater => tail = self.header().tail.load(Ordering::Relaxed),
+            }
         }
 
-        let idx = (head as usize) & self.mask;
-        let slot_offset = self.data_offset + idx * self.slot_size;
+        self.write_slot(tail, payload);
+        self.sequence(tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        self.notify();
+        Ok(())
+    }
+
+    /// Publishes a payload on the broadcast ring, overwriting the oldest message when the ring
+    /// is full. The slot is marked as being written first so subscribers detect they were lapped.
+    fn enqueue_broadcast(&mut self, payload: &[u8]) -> Result<()> {
+        let tail = self.header().tail.load(Ordering::Relaxed);
+
+        self.sequence(tail).store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.write_slot(tail, payload);
+        self.sequence(tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+
+        self.header().tail.store(tail.wrapping_add(1), Ordering::Release);
+        self.notify();
+        Ok(())
+    }
+
+    /// Returns `true` when the slot of the given index was fully written by its producer.
+    fn is_committed(&self, index: u64) -> bool {
+        match self.mode {
+            QueueMode::SPSC => true,
+            QueueMode::MPSC | QueueMode::BROADCAST => {
+                self.sequence(index).load(Ordering::Acquire) == index.wrapping_add(1)
+            }
+        }
+    }
 
-        let len_ptr = unsafe { self.mmap.as_mut_ptr().add(slot_offset) as *mut u32 };
-        let buf_ptr = unsafe { self.mmap.as_mut_ptr().add(4 + slot_offset) };
+    /// Copies the payload out of the slot of the given index.
+    fn read_slot(&self, index: u64) -> Result<Vec<u8>> {
+        let len_offset = self.slot_offset(index) + self.mode.slot_header() - 4;
+        let len_ptr = unsafe { self.mmap.as_ptr().add(len_offset) as *const u32 };
+        let buf_ptr = unsafe { self.mmap.as_ptr().add(len_offset + 4) };
 
         let len = unsafe { ptr::read_unaligned(len_ptr) as u32 } as usize;
-        if len > self.slot_size - 4 {
+        if len > self.max_payload_size() {
             bail!("corrupted length in slot");
         }
 
@@ -221,9 +609,50 @@ impl MmapQueue {
             ptr::copy_nonoverlapping(buf_ptr, out.as_mut_ptr(), len);
         }
 
-        let next_head = head.wrapping_add(1);
+        Ok(out)
+    }
+
+    /// This method is the second most important in this crate, it just takes the `head` index and 
+    /// tries to get the payload out of the current slot and increments the `head` index till it reaches 
+    /// the current `tail` index.
+    pub fn dequeue(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.mode == QueueMode::BROADCAST {
+            bail!("a broadcast queue is read through a subscriber")
+        }
+        let head = self.header().head.load(Ordering::Acquire);
+        let tail = self.header().tail.load(Ordering::Acquire);
+
+        // a claimed slot is only read once its producer committed it.
+        if tail == head || !self.is_committed(head) {
+            return Ok(None);
+        }
+
+        let (start, next_head) = self.next_message(head);
+        let out = self.read_slot(start)?;
+
+        if self.mode == QueueMode::MPSC {
+            // the slot is free again for the producer of the next lap.
+            self.sequence(head)
+                .store(head.wrapping_add(self.capacity as u64), Ordering::Release);
+        }
         self.header().head.store(next_head, Ordering::Release);
 
         Ok(Some(out))
     }
+
+    /// Returns every message between the `head` and the `tail` index without consuming them,
+    /// so a queue used as a log can be read again from its start.
+    pub fn peek_all(&self) -> Result<Vec<Vec<u8>>> {
+        let tail = self.header().tail.load(Ordering::Acquire);
+        let head = self.oldest(tail);
+
+        let mut out = Vec::new();
+        let mut index = head;
+        while index != tail && self.is_committed(index) {
+            let (start, next) = self.next_message(index);
+            out.push(self.read_slot(start)?);
+            index = next;
+        }
+        Ok(out)
+    }
 }
@@ -0,0 +1,264 @@
+use std::{
+    future::poll_fn,
+    hint::spin_loop,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread::{yield_now, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Ok, Result};
+
+use crate::MmapQueue;
+
+// longest sleep of the watcher thread before it checks whether its receiver was dropped.
+const WATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How a consumer waits for the producer once its queue is empty.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStrategy {
+    BUSY_SPIN,       // spins on the tail, the lowest latency at the cost of a whole core.
+    SPIN_YIELD(u32), // spins the given number of times, then yields the thread between two checks.
+    PARK(u32),       // spins the given number of times, then sleeps until the producer publishes.
+}
+
+/// Counts the idle iterations of a consumer loop and backs off according to its strategy.
+pub struct Backoff {
+    wait: WaitStrategy,
+    idle: u32, // checks in a row which found nothing to do.
+}
+
+impl Backoff {
+    pub fn new(wait: WaitStrategy) -> Self {
+        Self { wait, idle: 0 }
+    }
+
+    /// Called once there was work to do, the next wait starts spinning again.
+    pub fn reset(&mut self) {
+        self.idle = 0;
+    }
+
+    /// Waits before the next check. Returns `true` when the caller should park on its queue
+    /// instead, which only the `PARK` strategy asks for once it is done spinning.
+    pub fn snooze(&mut self) -> bool {
+        let spins = match self.wait {
+            WaitStrategy::BUSY_SPIN => u32::MAX,
+            WaitStrategy::SPIN_YIELD(spins) | WaitStrategy::PARK(spins) => spins,
+        };
+        self.idle = self.idle.saturating_add(1);
+        if self.idle <= spins {
+            spin_loop();
+            return false;
+        }
+        match self.wait {
+            WaitStrategy::PARK(_) => true,
+            _ => {
+                yield_now();
+                false
+            }
+        }
+    }
+}
+
+/// Sleeps while `word` holds `expected`, the futex is shared so a producer of another process
+/// mapping the same file can wake the caller.
+#[cfg(target_os = "linux")]
+pub(crate) fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            timeout
+                .as_ref()
+                .map_or(std::ptr::null(), |timeout| timeout as *const libc::timespec),
+        );
+    }
+}
+
+/// Wakes every thread sleeping on `word`.
+#[cfg(target_os = "linux")]
+pub(crate) fn futex_wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, i32::MAX);
+    }
+}
+
+// without futexes a parked consumer only yields, it wakes up on its own.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn futex_wait(_word: &AtomicU32, _expected: u32, _timeout: Option<Duration>) {
+    yield_now();
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn futex_wake(_word: &AtomicU32) {}
+
+/// The reading end of a queue, the consumer of a SPSC or MPSC queue or a subscriber of a
+/// broadcast queue.
+pub trait Consumer {
+    /// Reads the next message without waiting.
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// The queue the messages are read from.
+    fn queue(&self) -> &MmapQueue;
+
+    /// Reads the next message, waiting for the producer with the given strategy. Returns
+    /// `None` only when the timeout elapsed first.
+    fn recv_wait(
+        &mut self,
+        wait: WaitStrategy,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut backoff = Backoff::new(wait);
+        loop {
+            // the tail is read before the check, a message published after it wakes the park.
+            let observed = self.queue().header().tail.load(Ordering::Acquire);
+            if let Some(msg) = self.try_recv()? {
+                return Ok(Some(msg));
+            }
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return Ok(None),
+                },
+                None => None,
+            };
+            if backoff.snooze() {
+                self.queue().park(observed, remaining);
+            }
+        }
+    }
+}
+
+impl Consumer for MmapQueue {
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        self.dequeue()
+    }
+
+    fn queue(&self) -> &MmapQueue {
+        self
+    }
+}
+
+impl<C: Consumer + ?Sized> Consumer for &mut C {
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        (**self).try_recv()
+    }
+
+    fn queue(&self) -> &MmapQueue {
+        (**self).queue()
+    }
+}
+
+#[derive(Default)]
+struct Watch {
+    waiting: Option<(Waker, u64)>, // the task to wake and the tail index it last saw.
+    closed: bool,                  // set once the receiver is dropped.
+}
+
+/// Adapts a consumer to async code. A watcher thread parks on the tail of the queue for the
+/// waiting task and wakes it as soon as the producer publishes, so it runs on any executor.
+/// ```rust
+/// use memmap::{AsyncReceiver, MmapQueue};
+///
+/// let path = std::env::temp_dir().join("async_receiver_doc.dat");
+/// let mut producer = MmapQueue::create(&path, 4, 16).unwrap();
+/// let mut receiver = AsyncReceiver::new(MmapQueue::open(&path).unwrap()).unwrap();
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// runtime.block_on(async {
+///     let publisher = std::thread::spawn(move || producer.enqueue(b"ORDER1").unwrap());
+///     assert_eq!(receiver.recv().await.unwrap(), b"ORDER1".to_vec());
+///     publisher.join().unwrap();
+/// });
+/// # let _ = std::fs::remove_file(&path);
+/// ```
+pub struct AsyncReceiver<C: Consumer> {
+    consumer: C,
+    watch: Arc<(Mutex<Watch>, Condvar)>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl<C: Consumer> AsyncReceiver<C> {
+    pub fn new(consumer: C) -> Result<Self> {
+        let queue = consumer.queue().try_clone()?;
+        let watch = Arc::new((Mutex::new(Watch::default()), Condvar::new()));
+
+        let shared = watch.clone();
+        let watcher = std::thread::Builder::new()
+            .name("queue-watcher".into())
+            .spawn(move || {
+                let (lock, ready) = &*shared;
+                loop {
+                    let (waker, observed) = {
+                        let mut watch = lock.lock().unwrap();
+                        loop {
+                            if watch.closed {
+                                return;
+                            }
+                            if let Some(waiting) = watch.waiting.take() {
+                                break waiting;
+                            }
+                            watch = ready.wait(watch).unwrap();
+                        }
+                    };
+                    while queue.is_idle(observed) && !lock.lock().unwrap().closed {
+                        queue.park(observed, Some(WATCH_INTERVAL));
+                    }
+                    waker.wake();
+                }
+            })?;
+
+        Ok(Self {
+            consumer,
+            watch,
+            watcher: Some(watcher),
+        })
+    }
+
+    /// Polls for the next message, the task is woken once the producer publishes.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>>> {
+        let observed = self.consumer.queue().header().tail.load(Ordering::Acquire);
+        match self.consumer.try_recv() {
+            Result::Ok(Some(msg)) => return Poll::Ready(Ok(msg)),
+            Result::Ok(None) => {}
+            Err(err) => return Poll::Ready(Err(err)),
+        }
+
+        if self.consumer.queue().is_idle(observed) {
+            let (lock, ready) = &*self.watch;
+            lock.lock().unwrap().waiting = Some((cx.waker().clone(), observed));
+            ready.notify_one();
+        } else {
+            // a producer is still writing the next slot, poll again right away.
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Reads the next message, waiting for it without blocking the executor.
+    pub async fn recv(&mut self) -> Result<Vec<u8>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+impl<C: Consumer> Drop for AsyncReceiver<C> {
+    fn drop(&mut self) {
+        let (lock, ready) = &*self.watch;
+        lock.lock().unwrap().closed = true;
+        ready.notify_one();
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use anyhow::Result;
-use memmap::MmapQueue;
+use memmap::{
+    AsyncReceiver, Consumer, Journal, JournalReader, Lapped, MmapQueue, QueueMode, SeqCursor,
+    Subscriber, SyncPolicy, WaitStrategy,
+};
 use std::fs;
+use std::time::{Duration, Instant};
 
 fn tmp_path(name: &str) -> std::path::PathBuf {
     std::env::temp_dir().join(format!("mmap_queue_{}.dat", name))
@@ -32,3 +36,547 @@ fn producer_consumer_roundtrip() -> Result<()> {
     let _ = fs::remove_file(&p);
     Ok(())
 }
+
+#[test]
+fn queue_reports_len_and_full() -> Result<()> {
+    let p = tmp_path("test_len");
+    let _ = fs::remove_file(&p);
+    let mut q = MmapQueue::create(&p, 2, 16)?;
+
+    assert!(q.is_empty());
+    q.enqueue(b"one")?;
+    assert_eq!(q.len(), 1);
+    q.enqueue(b"two")?;
+    assert!(q.is_full());
+    assert!(q.enqueue(b"three").is_err());
+
+    q.dequeue()?;
+    assert!(!q.is_full());
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[test]
+fn open_refuses_files_of_another_layout() -> Result<()> {
+    let p = tmp_path("test_layout");
+    let _ = fs::remove_file(&p);
+    drop(MmapQueue::create(&p, 2, 16)?);
+    assert!(MmapQueue::open(&p).is_ok());
+
+    // the version follows the magic number at the start of the header.
+    let mut bytes = fs::read(&p)?;
+    bytes[8] += 1;
+    fs::write(&p, &bytes)?;
+    assert!(MmapQueue::open(&p).is_err());
+
+    bytes[8] -= 1;
+    bytes[0] ^= 0xff;
+    fs::write(&p, &bytes)?;
+    assert!(MmapQueue::open(&p).is_err());
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[test]
+fn peek_all_does_not_consume() -> Result<()> {
+    let p = tmp_path("test_peek");
+    let _ = fs::remove_file(&p);
+    let mut q = MmapQueue::create(&p, 4, 16)?;
+
+    q.enqueue(b"one")?;
+    q.enqueue(b"two")?;
+    q.dequeue()?;
+    q.enqueue(b"three")?;
+
+    assert_eq!(q.peek_all()?, vec![b"two".to_vec(), b"three".to_vec()]);
+    assert_eq!(q.len(), 2);
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[test]
+fn mpsc_producers_never_tear_messages() -> Result<()> {
+    let p = tmp_path("test_mpsc");
+    let _ = fs::remove_file(&p);
+    let mut cons = MmapQueue::create_with_mode(&p, 16, 64, QueueMode::MPSC)?;
+    assert_eq!(MmapQueue::open(&p)?.mode(), QueueMode::MPSC);
+    assert_eq!(cons.max_payload_size(), 68);
+
+    // every producer opens its own mapping, as separate processes would.
+    let producers: Vec<_> = (0..4u8)
+        .map(|producer| {
+            let p = p.clone();
+            std::thread::spawn(move || -> Result<()> {
+                let mut prod = MmapQueue::open(&p)?;
+                for i in 0..500u16 {
+                    let mut msg = vec![producer; 64];
+                    msg[..2].copy_from_slice(&i.to_le_bytes());
+                    while prod.enqueue(&msg).is_err() {
+                        std::hint::spin_loop();
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    let mut next = [0u16; 4];
+    while next.iter().any(|n| *n < 500) {
+        if let Some(msg) = cons.dequeue()? {
+            let producer = msg[2];
+            assert!(msg[2..].iter().all(|b| *b == producer), "torn message");
+            // messages of a single producer keep their order.
+            assert_eq!(
+                u16::from_le_bytes([msg[0], msg[1]]),
+                next[producer as usize]
+            );
+            next[producer as usize] += 1;
+        }
+    }
+    for producer in producers {
+        producer.join().unwrap()?;
+    }
+    assert!(cons.dequeue()?.is_none());
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[test]
+fn broadcast_subscribers_read_independently() -> Result<()> {
+    let p = tmp_path("test_broadcast");
+    let cursor = |name: &str| p.with_extension(format!("{name}.cursor"));
+    let _ = fs::remove_file(&p);
+    let _ = fs::remove_file(cursor("fast"));
+    let _ = fs::remove_file(cursor("slow"));
+
+    let mut prod = MmapQueue::create_with_mode(&p, 4, 16, QueueMode::BROADCAST)?;
+    let mut fast = Subscriber::open(&p, "fast")?;
+    let mut slow = Subscriber::open(&p, "slow")?;
+    assert!(prod.dequeue().is_err());
+
+    for i in 0..3u8 {
+        prod.enqueue(&[i])?;
+    }
+    // every subscriber reads every message.
+    for i in 0..3u8 {
+        assert_eq!(fast.recv()?, Some(vec![i]));
+    }
+    assert_eq!(fast.recv()?, None);
+    assert_eq!(slow.recv()?, Some(vec![0]));
+    assert_eq!(slow.lag(), 2);
+
+    // the cursor survives a restart of the subscriber.
+    drop(slow);
+    let mut slow = Subscriber::open(&p, "slow")?;
+    assert_eq!(slow.recv()?, Some(vec![1]));
+
+    // the producer never waits, it overwrites the slot of message 2 and 3.
+    for i in 3..7u8 {
+        prod.enqueue(&[i])?;
+    }
+    assert!(!prod.is_full());
+    assert_eq!(prod.len(), 4);
+    for i in 3..7u8 {
+        assert_eq!(fast.recv()?, Some(vec![i]));
+    }
+    let err = slow.recv().unwrap_err().downcast::<Lapped>()?;
+    assert_eq!(
+        err,
+        Lapped {
+            expected: 2,
+            oldest: 3
+        }
+    );
+    assert_eq!(slow.recv()?, Some(vec![3]));
+
+    // a restarted producer carries on in the same ring, the subscribers keep their place.
+    drop(prod);
+    let mut prod = MmapQueue::open_or_create_with_mode(&p, 4, 16, QueueMode::BROADCAST)?;
+    prod.enqueue(&[7])?;
+    assert_eq!(fast.recv()?, Some(vec![7]));
+    assert_eq!(slow.recv()?, Some(vec![4]));
+    // a ring of another size is created again.
+    drop(prod);
+    let prod = MmapQueue::open_or_create_with_mode(&p, 8, 16, QueueMode::BROADCAST)?;
+    assert_eq!(prod.len(), 0);
+    drop(prod);
+
+    let _ = fs::remove_file(&p);
+    let _ = fs::remove_file(cursor("fast"));
+    let _ = fs::remove_file(cursor("slow"));
+    Ok(())
+}
+
+#[test]
+fn parked_consumer_wakes_on_publish() -> Result<()> {
+    let p = tmp_path("test_park");
+    let _ = fs::remove_file(&p);
+    let mut cons = MmapQueue::create(&p, 4, 16)?;
+
+    // nothing is published, the wait gives up once the timeout elapsed.
+    let started = Instant::now();
+    let wait = WaitStrategy::PARK(16);
+    assert_eq!(cons.recv_wait(wait, Some(Duration::from_millis(20)))?, None);
+    assert!(started.elapsed() >= Duration::from_millis(20));
+
+    let producer = {
+        let p = p.clone();
+        std::thread::spawn(move || -> Result<()> {
+            let mut prod = MmapQueue::open(&p)?;
+            for i in 0..100u8 {
+                while prod.is_full() {
+                    std::thread::yield_now();
+                }
+                prod.enqueue(&[i])?;
+                if i % 10 == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            Ok(())
+        })
+    };
+    for i in 0..100u8 {
+        assert_eq!(cons.recv_wait(wait, None)?, Some(vec![i]));
+    }
+    producer.join().unwrap()?;
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[tokio::test]
+async fn async_receiver_reads_every_message() -> Result<()> {
+    let p = tmp_path("test_async");
+    let _ = fs::remove_file(&p);
+    let mut prod = MmapQueue::create_with_mode(&p, 8, 16, QueueMode::BROADCAST)?;
+    let mut receiver = AsyncReceiver::new(Subscriber::open(&p, "async")?)?;
+
+    let producer = std::thread::spawn(move || -> Result<()> {
+        for i in 0..5u8 {
+            std::thread::sleep(Duration::from_millis(2));
+            prod.enqueue(&[i])?;
+        }
+        Ok(())
+    });
+    for i in 0..5u8 {
+        assert_eq!(receiver.recv().await?, vec![i]);
+    }
+    producer.join().unwrap()?;
+
+    drop(receiver);
+    let _ = fs::remove_file(&p);
+    let _ = fs::remove_file(p.with_extension("async.cursor"));
+    Ok(())
+}
+
+#[test]
+fn zero_copy_slots_are_published_and_released_in_batches() -> Result<()> {
+    let p = tmp_path("test_zero_copy");
+    let _ = fs::remove_file(&p);
+    let mut prod = MmapQueue::create(&p, 4, 16)?;
+    let mut cons = MmapQueue::open(&p)?;
+
+    // nothing is visible before the batch is committed.
+    let mut batch = prod.reserve_batch();
+    for i in 0..3u8 {
+        batch.push(2)?.copy_from_slice(&[i, i]);
+    }
+    assert_eq!(batch.len(), 3);
+    assert!(cons.peek()?.is_none());
+    batch.commit();
+    assert_eq!(cons.len(), 3);
+
+    {
+        let read = cons.peek_batch(8)?;
+        assert_eq!(read.len(), 3);
+        assert_eq!(read.get(1), Some(&[1u8, 1][..]));
+        // the producer can't reuse the slots while they are borrowed.
+        assert!(prod.reserve_batch().push(1).is_ok());
+        let mut full = prod.reserve_batch();
+        full.push(1)?;
+        assert!(full.push(1).is_err());
+    }
+    // dropping the batch handed every slot back at once.
+    assert!(cons.is_empty());
+
+    let mut slot = prod.reserve(4)?;
+    slot.copy_from_slice(b"ABCD");
+    slot.commit();
+    assert!(prod.reserve(17).is_err());
+    assert_eq!(&*cons.peek()?.unwrap(), b"ABCD");
+    assert!(cons.peek()?.is_none());
+
+    // a multi-producer queue hands its slots back to the producers as well.
+    let mpsc = tmp_path("test_zero_copy_mpsc");
+    let _ = fs::remove_file(&mpsc);
+    let mut q = MmapQueue::create_with_mode(&mpsc, 2, 16, QueueMode::MPSC)?;
+    assert!(q.reserve(1).is_err());
+    for round in 0..3u8 {
+        q.enqueue(&[round])?;
+        q.enqueue(&[round + 1])?;
+        let read = q.peek_batch(2)?;
+        assert_eq!(
+            read.iter().collect::<Vec<_>>(),
+            vec![&[round][..], &[round + 1][..]]
+        );
+    }
+
+    // a broadcast slot abandoned before it was written keeps the message it held.
+    let broadcast = tmp_path("test_zero_copy_broadcast");
+    let _ = fs::remove_file(&broadcast);
+    let _ = fs::remove_file(broadcast.with_extension("zc.cursor"));
+    let mut q = MmapQueue::create_with_mode(&broadcast, 2, 16, QueueMode::BROADCAST)?;
+    q.enqueue(b"A")?;
+    q.enqueue(b"B")?;
+    let mut sub = Subscriber::open(&broadcast, "zc")?;
+    q.reserve(1)?;
+    assert_eq!(sub.recv()?, Some(b"A".to_vec()));
+    // once written the previous message is gone, even if the slot is never committed.
+    q.reserve(1)?.copy_from_slice(b"C");
+    assert_eq!(sub.recv()?, Some(b"B".to_vec()));
+    assert_eq!(sub.recv()?, None);
+    let mut slot = q.reserve(1)?;
+    slot.copy_from_slice(b"D");
+    slot.commit();
+    assert_eq!(sub.recv()?, Some(b"D".to_vec()));
+
+    let _ = fs::remove_file(&p);
+    let _ = fs::remove_file(&mpsc);
+    let _ = fs::remove_file(&broadcast);
+    let _ = fs::remove_file(broadcast.with_extension("zc.cursor"));
+    Ok(())
+}
+
+#[test]
+fn record_ring_takes_messages_of_any_size() -> Result<()> {
+    let p = tmp_path("test_records");
+    let _ = fs::remove_file(&p);
+    // 64 bytes of ring, a record takes its length and payload rounded up to 8 bytes.
+    assert!(MmapQueue::create_records(&p, 32, 20).is_err());
+    let mut prod = MmapQueue::create_records(&p, 64, 20)?;
+    let mut cons = MmapQueue::open(&p)?;
+    assert_eq!(cons.max_payload_size(), 20);
+
+    assert!(prod.enqueue(&[0; 21]).is_err());
+    // 8 + 16 + 24 bytes, the ring keeps 16 bytes which can't take a 20 bytes record.
+    prod.enqueue(b"")?;
+    prod.enqueue(b"ORDER1")?;
+    prod.enqueue(b"A LONGER ORDER ID")?;
+    assert_eq!(prod.len(), 48);
+    assert!(prod.is_full());
+    assert_eq!(cons.dequeue()?, Some(b"".to_vec()));
+    assert_eq!(cons.dequeue()?, Some(b"ORDER1".to_vec()));
+
+    // the record doesn't fit before the end of the ring, it wraps to its start.
+    prod.enqueue(b"WRAPPED ORDER")?;
+    assert_eq!(&*cons.peek()?.unwrap(), b"A LONGER ORDER ID");
+    let mut slot = prod.reserve(3)?;
+    slot.copy_from_slice(b"ZC1");
+    slot.commit();
+    assert!(prod.enqueue(&[0; 20]).is_err());
+
+    assert_eq!(
+        cons.peek_all()?,
+        vec![b"WRAPPED ORDER".to_vec(), b"ZC1".to_vec()]
+    );
+    {
+        let batch = cons.peek_batch(8)?;
+        assert_eq!(batch.get(1), Some(&b"ZC1"[..]));
+        assert_eq!(batch.get(0), Some(&b"WRAPPED ORDER"[..]));
+    }
+    assert!(cons.is_empty());
+
+    // the ring keeps going over many laps.
+    for i in 0..200usize {
+        let msg = vec![i as u8; i % 21];
+        prod.enqueue(&msg)?;
+        assert_eq!(cons.dequeue()?, Some(msg));
+    }
+
+    // reopening a ring of the same size keeps its messages, another size starts afresh.
+    prod.enqueue(b"KEPT")?;
+    drop((prod, cons));
+    let mut ring = MmapQueue::open_or_create_records(&p, 64, 20)?;
+    assert_eq!(ring.dequeue()?, Some(b"KEPT".to_vec()));
+    ring.enqueue(b"DROPPED")?;
+    drop(ring);
+    let mut ring = MmapQueue::open_or_create_records(&p, 128, 20)?;
+    assert!(ring.is_empty());
+    assert_eq!(ring.dequeue()?, None);
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
+
+#[test]
+fn journal_rotates_segments_and_survives_reopen() -> Result<()> {
+    let dir = std::env::temp_dir().join("journal_test_rotation");
+    let _ = fs::remove_dir_all(&dir);
+
+    // every segment holds two records of 24 + 8 bytes after its 8 bytes header.
+    let mut journal = Journal::open(&dir, 72, SyncPolicy::BATCH(2))?;
+    for seq_id in 0..5u128 {
+        journal.append(seq_id, &[seq_id as u8; 8])?;
+    }
+    assert!(journal.append(3, b"late").is_err());
+    drop(journal);
+    assert_eq!(fs::read_dir(&dir)?.count(), 3);
+
+    // appending continues after the last record.
+    let mut journal = Journal::open(&dir, 72, SyncPolicy::ALWAYS)?;
+    assert_eq!(journal.last_seq_id(), Some(4));
+    journal.append(7, &[7; 8])?;
+    drop(journal);
+
+    let seq_ids = |from| -> Result<Vec<u128>> {
+        JournalReader::open(&dir, from)?
+            .map(|record| record.map(|(seq_id, _)| seq_id))
+            .collect()
+    };
+    assert_eq!(seq_ids(0)?, vec![0, 1, 2, 3, 4, 7]);
+    assert_eq!(seq_ids(3)?, vec![3, 4, 7]);
+    assert_eq!(seq_ids(5)?, vec![7]);
+
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[test]
+fn journal_cuts_torn_records_and_detects_corruption() -> Result<()> {
+    let dir = std::env::temp_dir().join("journal_test_torn");
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    journal.append(1, b"first")?;
+    journal.append(2, b"second")?;
+    drop(journal);
+    let segment = fs::read_dir(&dir)?.next().unwrap()?.path();
+
+    // a crash in the middle of the last record leaves half of it behind.
+    let len = fs::metadata(&segment)?.len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&segment)?
+        .set_len(len - 3)?;
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    assert_eq!(journal.last_seq_id(), Some(1));
+    assert_eq!(journal.torn_bytes(), 24 + 6 - 3);
+    journal.append(2, b"again")?;
+    drop(journal);
+    let records: Vec<_> = JournalReader::open(&dir, 0)?.collect::<Result<_>>()?;
+    assert_eq!(
+        records,
+        vec![(1, b"first".to_vec()), (2, b"again".to_vec())]
+    );
+
+    // a last record of the right length which fails its crc was torn as well.
+    let mut bytes = fs::read(&segment)?;
+    *bytes.last_mut().unwrap() ^= 0xff;
+    fs::write(&segment, bytes)?;
+    let journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    assert_eq!(journal.last_seq_id(), Some(1));
+    assert_eq!(journal.torn_bytes(), 24 + 5);
+    drop(journal);
+
+    // a flipped byte in the payload of the first record fails its crc.
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    journal.append(2, b"again")?;
+    drop(journal);
+    let mut bytes = fs::read(&segment)?;
+    bytes[8 + 24] ^= 0xff;
+    fs::write(&segment, bytes)?;
+    let mut reader = JournalReader::open(&dir, 0)?;
+    assert!(reader.next().unwrap().is_err());
+    assert!(reader.next().is_none());
+    // the records after it are intact, so the journal is corrupted rather than torn.
+    assert!(Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS).is_err());
+    assert_eq!(fs::metadata(&segment)?.len(), 8 + 2 * 24 + 5 + 5);
+
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[test]
+fn journal_drops_a_segment_created_without_its_header() -> Result<()> {
+    let dir = std::env::temp_dir().join("journal_test_blank");
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    journal.append(1, b"first")?;
+    journal.append(2, b"second")?;
+    drop(journal);
+
+    // a crash right after the next segment was created leaves it without its magic.
+    let blank = dir.join(format!("{:032x}.wal", 3));
+    fs::File::create(&blank)?;
+    let records: Vec<_> = JournalReader::open(&dir, 0)?.collect::<Result<_>>()?;
+    assert_eq!(records.len(), 2);
+
+    let mut journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    assert!(!blank.exists());
+    assert_eq!(journal.last_seq_id(), Some(2));
+    assert_eq!(journal.torn_bytes(), 0);
+    journal.append(3, b"third")?;
+    drop(journal);
+    let records: Vec<_> = JournalReader::open(&dir, 2)?.collect::<Result<_>>()?;
+    assert_eq!(
+        records,
+        vec![(2, b"second".to_vec()), (3, b"third".to_vec())]
+    );
+
+    // part of the magic is no better than none.
+    fs::write(&blank, [0x31, 0x4c])?;
+    let journal = Journal::open(&dir, 1 << 20, SyncPolicy::ALWAYS)?;
+    assert_eq!(journal.last_seq_id(), Some(3));
+    assert_eq!(journal.torn_bytes(), 2);
+    drop(journal);
+
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[test]
+fn cursor_is_shared_through_the_file() -> Result<()> {
+    let p = tmp_path("test_cursor");
+    let _ = fs::remove_file(&p);
+
+    let mut writer = SeqCursor::open(&p)?;
+    let reader = SeqCursor::open(&p)?;
+    assert_eq!(reader.load(), None);
+
+    writer.store(41);
+    writer.store(u64::MAX as u128 + 1);
+    assert_eq!(reader.load(), Some(u64::MAX as u128 + 1));
+
+    // the stored sequence id outlives the cursor.
+    drop(writer);
+    drop(reader);
+    assert_eq!(SeqCursor::open(&p)?.load(), Some(u64::MAX as u128 + 1));
+
+    // a writer which dies in the middle of the next store leaves the version of the first
+    // slot odd, the value of the previous store is read instead.
+    let mut bytes = fs::read(&p)?;
+    bytes[8..16].copy_from_slice(&5u64.to_le_bytes());
+    bytes[16..24].copy_from_slice(&42u64.to_le_bytes());
+    fs::write(&p, &bytes)?;
+    let mut writer = SeqCursor::open(&p)?;
+    assert_eq!(writer.load(), Some(u64::MAX as u128 + 1));
+    writer.store(43);
+    assert_eq!(writer.load(), Some(43));
+    drop(writer);
+
+    // a torn first store reads as nothing stored.
+    fs::remove_file(&p)?;
+    drop(SeqCursor::open(&p)?);
+    let mut bytes = fs::read(&p)?;
+    bytes[8..16].copy_from_slice(&1u64.to_le_bytes());
+    fs::write(&p, &bytes)?;
+    assert_eq!(SeqCursor::open(&p)?.load(), None);
+
+    let _ = fs::remove_file(&p);
+    Ok(())
+}
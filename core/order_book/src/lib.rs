@@ -1,26 +1,31 @@
 pub mod limit;
 pub mod order;
+pub mod stop;
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use ordered_float::OrderedFloat;
 use skiplist::SkipMap;
 
 use crate::{limit::Limit, order::Order};
 
-use core_utils::{RawOrder, Side};
+use core_utils::{L2Snapshot, L3Order, L3Snapshot, Level, Price, Qty, RawOrder, Side};
 
 /// This struct holds the core logic for managing the pending orders
 /// or the orders that are currently not processed by the matching enigne.
 pub struct LimitOrderBook {
     pub book_id: String, // The unqiue book id for partionining the exchange
-    pub ask_list: SkipMap<OrderedFloat<f64>, Rc<RefCell<Limit>>>, // skip list for storing all the ASK limit nodes.
-    pub bid_list: SkipMap<OrderedFloat<f64>, Rc<RefCell<Limit>>>, // skip list for storing all the BID limit nodes.
-    pub ask_map: HashMap<OrderedFloat<f64>, Rc<RefCell<Limit>>>, // hash map for fast lookups for the ASK limit nodes.
-    pub bid_map: HashMap<OrderedFloat<f64>, Rc<RefCell<Limit>>>, // hash map for fast loopups for the BID limit nodes.
+    pub ask_list: SkipMap<Price, Rc<RefCell<Limit>>>, // skip list for storing all the ASK limit nodes.
+    pub bid_list: SkipMap<Price, Rc<RefCell<Limit>>>, // skip list for storing all the BID limit nodes.
+    pub ask_map: HashMap<Price, Rc<RefCell<Limit>>>, // hash map for fast lookups for the ASK limit nodes.
+    pub bid_map: HashMap<Price, Rc<RefCell<Limit>>>, // hash map for fast loopups for the BID limit nodes.
     pub ord_map: HashMap<String, Rc<RefCell<Order>>>, // hash map for fast lookups for all the Orders in the limit order book
     pub best_ask: Option<Rc<RefCell<Order>>>, // A reference to the best ASK order, typically the front node's head order in the ASK skip list.
     pub best_bid: Option<Rc<RefCell<Order>>>, // A reference to the best BID order, typically the back node's head order in the BID skip list.
+    pub changed_levels: HashSet<(Side, Price)>, // limit nodes changed since the last call of `take_changed_levels`.
 }
 
 impl From<String> for LimitOrderBook {
@@ -34,6 +39,7 @@ impl From<String> for LimitOrderBook {
             ord_map: HashMap::new(),
             best_ask: None,
             best_bid: None,
+            changed_levels: HashSet::new(),
         }
     }
 }
@@ -47,7 +53,7 @@ impl LimitOrderBook {
     /// assert!(limit_order_book.best_ask.is_none());
     /// assert!(limit_order_book.best_bid.is_none());
     /// // create a raw order and then pass to the order book for insertion
-    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"12121".into(),quote:"BTCINR".into(),price:1000.11, size: 10,side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT };
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"12121".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
     ///
     /// limit_order_book.insert(raw_order);
     ///
@@ -56,7 +62,7 @@ impl LimitOrderBook {
     /// assert!(!limit_order_book.best_bid.is_none());
     /// ```
     pub fn insert(&mut self, raw_order: RawOrder) {
-        let price = raw_order.price.clone();
+        let price = raw_order.price;
         // generates the order from the raw order
         let order = Rc::new(RefCell::new(Order::from(raw_order)));
         // gets the relevant list and the map as the mutable reference.
@@ -66,9 +72,10 @@ impl LimitOrderBook {
         };
         // if the limit node already exists then fetch from the map or else insert the limit node in the skip list and also insert in map
         // then finally get the limit node.
-        let limit = map.entry(OrderedFloat(price)).or_insert_with(|| {
+        self.changed_levels.insert((order.borrow().side, price));
+        let limit = map.entry(price).or_insert_with(|| {
             let limit = Rc::new(RefCell::new(Limit::new(price)));
-            list.insert(OrderedFloat(price), limit.clone());
+            list.insert(price, limit.clone());
             limit.clone()
         });
         // if the tail of this limit node is None that means, the limit node was created now only
@@ -76,141 +83,451 @@ impl LimitOrderBook {
         // else update the generated order as the new tail and update the prev and prev tail's next pointer.
         // ofcourse we have to update the total volume in the limit node.
         let mut limit_mut_borrowed = limit.borrow_mut();
-        limit_mut_borrowed.vol += order.borrow().size.clone();
+        limit_mut_borrowed.vol += order.borrow().visible;
+        limit_mut_borrowed.reserve += order.borrow().size - order.borrow().visible;
         if let Some(ref mut tail) = limit_mut_borrowed.tail {
             tail.borrow_mut().next = Some(order.clone());
-            order.borrow_mut().prev = Some(Rc::downgrade(&tail));
+            order.borrow_mut().prev = Some(Rc::downgrade(tail));
         } else {
             limit_mut_borrowed.head = Some(order.clone());
         }
         limit_mut_borrowed.tail = Some(order.clone());
+        drop(limit_mut_borrowed);
 
-        // if the best order (ASK or BID) is empty or None then update this order as the
-        // best order from the relevant side.
-        match order.borrow().side {
-            Side::ASK => {
-                if self.best_ask.is_none() {
-                    self.best_ask = Some(order.clone());
-                }
-            }
-            Side::BID => {
-                if self.best_bid.is_none() {
-                    self.best_bid = Some(order.clone());
-                }
-            }
-        };
-
-        // finally, insert the order in the order map for fast lookups.
+        // insert the order in the order map for fast lookups.
         self.ord_map
             .insert(order.borrow().order_id.clone(), order.clone());
+
+        // finally, the order may have a better price than the best order of its side.
+        let side = order.borrow().side;
+        self.update_best(side);
     }
 
-    /// This method returns the total volume at particular limit price.
+    /// This method returns the total displayed volume at particular limit price, the hidden
+    /// reserve of iceberg orders is not part of it.
     /// ```rust
     /// let mut limit_order_book= lob::LimitOrderBook::from(String::from("1"));
-    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:1000.11, size: 10,side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT };
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
     ///
     /// limit_order_book.insert(raw_order);
-    /// let depth=limit_order_book.depth(core_utils::Side::BID,1000.11);
+    /// let depth=limit_order_book.depth(core_utils::Side::BID,core_utils::Price(100011));
     /// assert!(depth.is_some());
-    /// assert_eq!(depth.unwrap(),10);
+    /// assert_eq!(depth.unwrap(),core_utils::Qty(10));
     /// ```
-    pub fn depth(&self, side: Side, limit: f64) -> Option<u64> {
+    pub fn depth(&self, side: Side, limit: Price) -> Option<Qty> {
         let map = match side {
             Side::ASK => &self.ask_map,
             Side::BID => &self.bid_map,
         };
 
-        if let Some(node) = map.get(&OrderedFloat(limit)) {
-            return Some(node.borrow().vol.clone());
+        if let Some(node) = map.get(&limit) {
+            return Some(node.borrow().vol);
         }
         None
     }
 
-    /// This method removes the order from the book.
-    // For now I have to figure out what must be returned.
+    /// This method returns the total volume resting on the given side that can be traded
+    /// at the given price, including the hidden reserve of iceberg orders, walking the limit
+    /// nodes from the best one. If the price is `None`
+    /// then every limit node is tradable. It stops counting as soon as `quantity` is reached.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// for (i, price) in [10000, 10100, 10200].iter().enumerate() {
+    ///     let raw_order=core_utils::RawOrder{ seq_id:i as u128,order_id:format!("ORDER{i}"),quote:"BTCINR".into(),price:core_utils::Price(*price), size: core_utils::Qty(10),side:core_utils::Side::ASK, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
+    ///     book.insert(raw_order);
+    /// }
+    ///
+    /// assert_eq!(book.liquidity(core_utils::Side::ASK, Some(core_utils::Price(10100)), core_utils::Qty(100)), core_utils::Qty(20));
+    /// assert_eq!(book.liquidity(core_utils::Side::ASK, Some(core_utils::Price(9900)), core_utils::Qty(100)), core_utils::Qty(0));
+    /// assert_eq!(book.liquidity(core_utils::Side::ASK, None, core_utils::Qty(15)), core_utils::Qty(20));
+    /// ```
+    pub fn liquidity(&self, side: Side, price: Option<Price>, quantity: Qty) -> Qty {
+        let mut total = Qty::ZERO;
+        for limit in self.levels(side) {
+            let tradable = match (side, price) {
+                (_, None) => true,
+                (Side::ASK, Some(price)) => limit.borrow().price <= price,
+                (Side::BID, Some(price)) => limit.borrow().price >= price,
+            };
+            if !tradable || total >= quantity {
+                break;
+            }
+            total += limit.borrow().vol + limit.borrow().reserve;
+        }
+        total
+    }
+
+    /// Returns the limit nodes of the given side from the best price, asks are walked
+    /// ascending and bids descending.
+    pub fn levels(&self, side: Side) -> Box<dyn Iterator<Item = &Rc<RefCell<Limit>>> + '_> {
+        match side {
+            Side::ASK => Box::new(self.ask_list.iter().map(|(_, limit)| limit)),
+            Side::BID => Box::new(self.bid_list.iter().rev().map(|(_, limit)| limit)),
+        }
+    }
+
+    /// This method returns the aggregated (level 2) snapshot of the top `depth` price levels
+    /// of both sides, with the displayed volume and the number of orders of every level.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// for (i, price) in [10000, 10000, 10100, 10200].iter().enumerate() {
+    ///     let raw_order=core_utils::RawOrder{ seq_id:i as u128,order_id:format!("ORDER{i}"),quote:"BTCINR".into(),price:core_utils::Price(*price), size: core_utils::Qty(10),side:core_utils::Side::ASK, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
+    ///     book.insert(raw_order);
+    /// }
+    ///
+    /// let snapshot=book.l2_snapshot(2);
+    /// assert_eq!(snapshot.asks, vec![
+    ///     core_utils::Level{ price:core_utils::Price(10000), vol:core_utils::Qty(20), orders:2 },
+    ///     core_utils::Level{ price:core_utils::Price(10100), vol:core_utils::Qty(10), orders:1 },
+    /// ]);
+    /// assert!(snapshot.bids.is_empty());
+    /// ```
+    pub fn l2_snapshot(&self, depth: usize) -> L2Snapshot {
+        let levels = |side| {
+            self.levels(side)
+                .take(depth)
+                .filter_map(|limit| self.level(side, limit.borrow().price))
+                .collect()
+        };
+
+        L2Snapshot {
+            quote: self.book_id.clone(),
+            asks: levels(Side::ASK),
+            bids: levels(Side::BID),
+        }
+    }
+
+    /// This method returns the order by order (level 3) snapshot of the book, every resting
+    /// order with its displayed size, in price and then queue order.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// for (i, price) in [10000, 10100, 10000].iter().enumerate() {
+    ///     let raw_order=core_utils::RawOrder{ seq_id:i as u128,order_id:format!("ORDER{i}"),quote:"BTCINR".into(),price:core_utils::Price(*price), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
+    ///     book.insert(raw_order);
+    /// }
+    ///
+    /// let snapshot=book.l3_snapshot();
+    /// let order_ids: Vec<_> = snapshot.bids.iter().map(|order| order.order_id.as_str()).collect();
+    /// assert_eq!(order_ids, vec!["ORDER1", "ORDER0", "ORDER2"]);
+    /// ```
+    pub fn l3_snapshot(&self) -> L3Snapshot {
+        let orders = |side| {
+            self.levels(side)
+                .flat_map(|limit| limit.borrow().orders().collect::<Vec<_>>())
+                .map(|order| {
+                    let order = order.borrow();
+                    L3Order {
+                        seq_id: order.seq_id,
+                        order_id: order.order_id.clone(),
+                        price: order.price,
+                        size: order.visible,
+                    }
+                })
+                .collect()
+        };
+
+        L3Snapshot {
+            quote: self.book_id.clone(),
+            asks: orders(Side::ASK),
+            bids: orders(Side::BID),
+        }
+    }
+
+    /// Returns a copy of every resting order, asks then bids, each side from the best price
+    /// and in queue order, so inserting them again in this order rebuilds the same book.
+    pub fn resting_orders(&self) -> Vec<Order> {
+        [Side::ASK, Side::BID]
+            .into_iter()
+            .flat_map(|side| self.levels(side))
+            .flat_map(|limit| limit.borrow().orders().collect::<Vec<_>>())
+            .map(|order| order.borrow().clone())
+            .collect()
+    }
+
+    /// Inserts an order taken from a snapshot of the book at the back of its limit node, the
+    /// order keeps the displayed part and the executed quantity it had when it was taken.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"ICEBERG".into(),quote:"BTCINR".into(),price:core_utils::Price(10000), size: core_utils::Qty(8),side:core_utils::Side::ASK, order_type:core_utils::OrderType::LIMIT, display_size:Some(core_utils::Qty(5)), ..Default::default() };
+    /// book.restore(raw_order, core_utils::Qty(3), core_utils::Qty(2));
+    ///
+    /// assert_eq!(book.depth(core_utils::Side::ASK,core_utils::Price(10000)), Some(core_utils::Qty(3)));
+    /// assert_eq!(book.ord_map["ICEBERG"].borrow().filled, core_utils::Qty(2));
+    /// ```
+    pub fn restore(&mut self, raw_order: RawOrder, visible: Qty, filled: Qty) {
+        let order_id = raw_order.order_id.clone();
+        self.insert(raw_order);
+        let order = match self.ord_map.get(&order_id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        let (side, price, shown) = {
+            let order = order.borrow();
+            (order.side, order.price, order.visible)
+        };
+        let visible = std::cmp::min(visible, order.borrow().size);
+        let map = match side {
+            Side::ASK => &self.ask_map,
+            Side::BID => &self.bid_map,
+        };
+        if let Some(limit) = map.get(&price) {
+            let mut limit = limit.borrow_mut();
+            limit.vol = limit.vol - shown + visible;
+            limit.reserve = limit.reserve + shown - visible;
+        }
+        order.borrow_mut().visible = visible;
+        order.borrow_mut().filled = filled;
+    }
+
+    /// This method executes the given quantity against a resting order, the size of the order
+    /// and the total volume of its limit node are reduced by the traded quantity, at most the
+    /// displayed quantity of the order is traded. If the order is completely filled then it is
+    /// removed from the book. When the peak of an iceberg order is filled, the next peak is
+    /// taken from its reserve and the order moves to the back of the queue of its limit node.
+    /// Returns the remaining size of the order or `None` if the order does not exist.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
+    /// book.insert(raw_order);
+    ///
+    /// assert_eq!(book.execute("order_id_10232", core_utils::Qty(4)), Some(core_utils::Qty(6)));
+    /// assert_eq!(book.depth(core_utils::Side::BID,core_utils::Price(100011)), Some(core_utils::Qty(6)));
+    /// // filling the rest of the order removes it from the book.
+    /// assert_eq!(book.execute("order_id_10232", core_utils::Qty(6)), Some(core_utils::Qty(0)));
+    /// assert!(book.depth(core_utils::Side::BID,core_utils::Price(100011)).is_none());
+    /// ```
+    pub fn execute(&mut self, order_id: &str, quantity: Qty) -> Option<Qty> {
+        let order = self.ord_map.get(order_id)?.clone();
+        let quantity = std::cmp::min(quantity, order.borrow().visible);
+
+        let map = match order.borrow().side {
+            Side::ASK => &self.ask_map,
+            Side::BID => &self.bid_map,
+        };
+
+        // the traded quantity leaves the limit node as well as the order.
+        if let Some(limit) = map.get(&order.borrow().price) {
+            limit.borrow_mut().vol -= quantity;
+        }
+        self.changed_levels
+            .insert((order.borrow().side, order.borrow().price));
+        order.borrow_mut().size -= quantity;
+        order.borrow_mut().visible -= quantity;
+        order.borrow_mut().filled += quantity;
+
+        let (remaining, visible, price) = {
+            let order = order.borrow();
+            (order.size, order.visible, order.price)
+        };
+        if remaining.is_zero() {
+            self.remove(order_id.to_string());
+        } else if visible.is_zero() {
+            self.requeue(order_id, price, remaining);
+        }
+
+        Some(remaining)
+    }
+
+    /// This method amends the price and/or the size of a resting order.
+    /// Decreasing the size keeps the time priority of the order, the order and its limit node are
+    /// updated in place. Changing the price or increasing the size loses the time priority, the
+    /// order is moved to the back of the queue of the limit node of the new price.
+    /// Returns `false` if the order does not exist.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, time_in_force:core_utils::TimeInForce::DAY, ..Default::default() };
+    /// book.insert(raw_order);
+    ///
+    /// assert!(book.amend("order_id_10232", core_utils::Price(100011), core_utils::Qty(4)));
+    /// assert_eq!(book.depth(core_utils::Side::BID,core_utils::Price(100011)), Some(core_utils::Qty(4)));
+    /// // moving the order to another limit node.
+    /// assert!(book.amend("order_id_10232", core_utils::Price(100012), core_utils::Qty(4)));
+    /// assert!(book.depth(core_utils::Side::BID,core_utils::Price(100011)).is_none());
+    /// assert_eq!(book.depth(core_utils::Side::BID,core_utils::Price(100012)), Some(core_utils::Qty(4)));
+    /// assert_eq!(book.ord_map["order_id_10232"].borrow().time_in_force, core_utils::TimeInForce::DAY);
+    /// ```
+    pub fn amend(&mut self, order_id: &str, price: Price, size: Qty) -> bool {
+        let order = match self.ord_map.get(order_id) {
+            Some(order) => order.clone(),
+            None => return false,
+        };
+
+        if order.borrow().price == price && size <= order.borrow().size {
+            let map = match order.borrow().side {
+                Side::ASK => &self.ask_map,
+                Side::BID => &self.bid_map,
+            };
+            let (old_size, old_visible) = (order.borrow().size, order.borrow().visible);
+            let visible = std::cmp::min(old_visible, size);
+            if let Some(limit) = map.get(&price) {
+                let mut limit = limit.borrow_mut();
+                limit.vol -= old_visible - visible;
+                limit.reserve -= (old_size - old_visible) - (size - visible);
+            }
+            order.borrow_mut().size = size;
+            order.borrow_mut().visible = visible;
+            self.changed_levels.insert((order.borrow().side, price));
+            return true;
+        }
+
+        self.requeue(order_id, price, size);
+        true
+    }
+
+    /// Moves the order to the back of the queue of the limit node of the given price, the
+    /// order is re-inserted as a new order which keeps the sequence id and the executed
+    /// quantity of the original order.
+    fn requeue(&mut self, order_id: &str, price: Price, size: Qty) {
+        let order = match self.ord_map.get(order_id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+
+        let raw_order = {
+            let order = order.borrow();
+            RawOrder::default()
+                .with_seq_id(order.seq_id)
+                .with_order_id(order.order_id.clone())
+                .with_quote(order.quote.clone())
+                .with_price(price)
+                .with_size(size)
+                .with_side(order.side)
+                .with_order_type(order.order_type)
+                .with_time_in_force(order.time_in_force)
+                .with_post_only(order.post_only)
+                .with_owner_id(order.owner_id)
+                .with_self_trade_prevention(order.self_trade_prevention)
+                .with_display_size(order.display_size)
+                .to_owned()
+        };
+        let filled = order.borrow().filled;
+        self.remove(order_id.to_string());
+        self.insert(raw_order);
+        if let Some(order) = self.ord_map.get(order_id) {
+            order.borrow_mut().filled = filled;
+        }
+    }
+
+    /// This method removes the order from the book and returns it with its remaining size,
+    /// or `None` if the order does not exist. The best order of the side is kept up to date.
     ///```rust
     /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
-    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:1000.11, size: 10,side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT };
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
     /// book.insert(raw_order);
     ///
-    /// let depth=book.depth(core_utils::Side::BID,1000.11);
+    /// let depth=book.depth(core_utils::Side::BID,core_utils::Price(100011));
     /// assert!(depth.is_some());
-    /// assert_eq!(depth.unwrap(),10);
+    /// assert_eq!(depth.unwrap(),core_utils::Qty(10));
     /// // removing the order now
-    /// book.remove("order_id_10232".into());
+    /// let order=book.remove("order_id_10232".into());
+    /// assert_eq!(order.unwrap().size,core_utils::Qty(10));
     /// // since the order has been removed now, so the total volume
     /// // within that limit node must be reduced to the intial volume.
-    /// let depth=book.depth(core_utils::Side::BID,1000.11);
+    /// let depth=book.depth(core_utils::Side::BID,core_utils::Price(100011));
     /// assert!(depth.is_none());
+    /// assert!(book.best_bid.is_none());
+    /// assert!(book.remove("order_id_10232".into()).is_none());
     /// ```
-    //
-    pub fn remove(&mut self, order_id: String) {
+    pub fn remove(&mut self, order_id: String) -> Option<Order> {
         // try to remove the order from the order map
-        if let Some(ref order) = self.ord_map.remove(&order_id) {
-            // then take the prev order and the next order,
-            // so now the order does not have prev or next pointer.
-            let prev_order = order.borrow_mut().prev.take();
-            let next_order = order.borrow_mut().next.take();
-
-            // to remove the order from the doubly linked list,
-            // update the next of prev's order as the next of the order that
-            // is been removed.
-            if let Some(prev) = prev_order.clone() {
-                if prev.upgrade().is_some() {
-                    prev.upgrade().unwrap().borrow_mut().next = next_order.clone();
-                } else {
-                    // upgrade failed
-                }
-            }
+        let order = self.ord_map.remove(&order_id)?;
+        let (price, side) = (order.borrow().price, order.borrow().side);
+
+        // then take the prev order and the next order,
+        // so now the order does not have prev or next pointer.
+        let prev_order = order
+            .borrow_mut()
+            .prev
+            .take()
+            .and_then(|prev| prev.upgrade());
+        let next_order = order.borrow_mut().next.take();
+
+        // to remove the order from the doubly linked list,
+        // update the next of prev's order as the next of the order that
+        // is been removed.
+        if let Some(prev) = prev_order.as_ref() {
+            prev.borrow_mut().next = next_order.clone();
+        }
 
-            // similarly, update the prev of next's order as the prev of the order
-            // that is been removed.
-            if let Some(next) = next_order.clone() {
-                next.borrow_mut().prev = prev_order.clone();
-            }
+        // similarly, update the prev of next's order as the prev of the order
+        // that is been removed.
+        if let Some(next) = next_order.as_ref() {
+            next.borrow_mut().prev = prev_order.as_ref().map(Rc::downgrade);
+        }
 
-            let map = match order.borrow().side {
-                Side::ASK => &mut self.ask_map,
-                Side::BID => &mut self.bid_map,
-            };
+        let (list, map) = match side {
+            Side::ASK => (&mut self.ask_list, &mut self.ask_map),
+            Side::BID => (&mut self.bid_list, &mut self.bid_map),
+        };
 
+        if let Some(limit) = map.get(&price).cloned() {
+            let mut limit = limit.borrow_mut();
             // update the total volume of the limit node by substracting the size of the removed order.
-            if let Some(limit) = map.get(&OrderedFloat(order.borrow().price)) {
-                limit.borrow_mut().vol -= order.borrow().size.clone();
+            limit.vol -= order.borrow().visible;
+            limit.reserve -= order.borrow().size - order.borrow().visible;
+
+            // the removed order was the head (or the tail) of the limit node, so the next
+            // (or the prev) order takes its place.
+            if prev_order.is_none() {
+                limit.head = next_order.clone();
+            }
+            if next_order.is_none() {
+                limit.tail = prev_order.clone();
             }
 
-            // if the prev and next are None then that means the limit node is empty
-            // hence remove the limit node from the map and the skip list.
-            if prev_order.is_none() && next_order.is_none() {
-                map.remove(&OrderedFloat(order.borrow().price.clone()));
-                let list = match order.borrow().side {
-                    Side::ASK => &mut self.ask_list,
-                    Side::BID => &mut self.bid_list,
-                };
-
-                list.remove(&OrderedFloat(order.borrow().price));
-            } else if prev_order.is_none() && next_order.is_some() {
-                if let Some(limit) = map.get(&OrderedFloat(order.borrow().price.clone())) {
-                    limit.borrow_mut().head = next_order;
-                }
-            } else if prev_order.is_some() && next_order.is_none() {
-                if let Some(limit) = map.get(&OrderedFloat(order.borrow().price.clone())) {
-                    limit.borrow_mut().tail = next_order;
-                }
+            // if the limit node has no orders left then remove it from the map and the skip list.
+            if limit.head.is_none() {
+                map.remove(&price);
+                list.remove(&price);
             }
+        }
+
+        // the removed order may have been the best order of its side.
+        self.changed_levels.insert((side, price));
+        self.update_best(side);
+
+        let removed = order.borrow().clone();
+        Some(removed)
+    }
 
-            // figure something what is to be returned,
-            // so that the order manager or the sequencer can
-            // emit event as Cancelled.
+    /// Returns the limit nodes changed since the last call, sorted by side and price, so the
+    /// changes of the book can be published as level updates.
+    /// ```rust
+    /// let mut book= lob::LimitOrderBook::from(String::from("BOOK"));
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
+    /// book.insert(raw_order);
+    ///
+    /// assert_eq!(book.take_changed_levels(), vec![(core_utils::Side::BID, core_utils::Price(100011))]);
+    /// assert!(book.take_changed_levels().is_empty());
+    /// ```
+    pub fn take_changed_levels(&mut self) -> Vec<(Side, Price)> {
+        let mut levels: Vec<_> = self.changed_levels.drain().collect();
+        levels.sort();
+        levels
+    }
+
+    /// Returns the aggregated view of a single limit node, `None` if no order rests at the price.
+    pub fn level(&self, side: Side, price: Price) -> Option<Level> {
+        let map = match side {
+            Side::ASK => &self.ask_map,
+            Side::BID => &self.bid_map,
         };
+        map.get(&price).map(|limit| {
+            let limit = limit.borrow();
+            Level {
+                price: limit.price,
+                vol: limit.vol,
+                orders: limit.orders().count() as u32,
+            }
+        })
     }
 
-    /// This method is used for updating the best orders
+    /// This method is used for updating the best orders, `insert` and `remove` already
+    /// keep them up to date.
     /// ```rust
     /// // creating lob and inserting dummy order
     /// let mut lob=lob::LimitOrderBook::from(String::from("BOOK"));
-    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:1000.11, size: 10,side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT };
+    /// let raw_order=core_utils::RawOrder{ seq_id:1,order_id:"order_id_10232".into(),quote:"BTCINR".into(),price:core_utils::Price(100011), size: core_utils::Qty(10),side:core_utils::Side::BID, order_type:core_utils::OrderType::LIMIT, ..Default::default() };
     /// lob.insert(raw_order);
     ///
     /// // whoever has the limit order book can update the best order.
@@ -228,22 +545,22 @@ impl LimitOrderBook {
 
     /// This method will grab the first limit node from the [`Side::ASK`]'s skip list
     /// and get the `head` pointer from the limit node and mark it as the best ask order.
+    /// If the skip list is empty then there is no best ask order anymore.
     fn update_ask(&mut self) {
-        if let Some((_, limit)) = self.ask_list.front() {
-            if let Some(head) = limit.borrow().head.clone() {
-                self.best_ask = Some(head);
-            }
-        }
+        self.best_ask = self
+            .ask_list
+            .front()
+            .and_then(|(_, limit)| limit.borrow().head.clone());
     }
 
     /// This method will grab the last limit node from the [`Side::BID`]'s skip list
     /// and get the `head` pointer from the limit node and mark it as the best ask order.
+    /// If the skip list is empty then there is no best bid order anymore.
     fn update_bid(&mut self) {
-        if let Some((_, limit)) = self.bid_list.back() {
-            if let Some(head) = limit.borrow().head.clone() {
-                self.best_bid = Some(head);
-            }
-        }
+        self.best_bid = self
+            .bid_list
+            .back()
+            .and_then(|(_, limit)| limit.borrow().head.clone());
     }
 }
 
@@ -262,10 +579,11 @@ mod tests {
             seq_id: 1,
             order_id: "ORDER1".into(),
             quote: "BTCETH".into(),
-            price: 100.10,
-            size: 10,
+            price: Price(10010),
+            size: Qty(10),
             side: Side::ASK,
             order_type: OrderType::LIMIT,
+            ..Default::default()
         };
 
         lob.insert(raw_order);
@@ -286,10 +604,11 @@ mod tests {
                 seq_id: i,
                 order_id: format!("ORDER{:?}", i),
                 quote: "BTCETH".into(),
-                price: 100.10,
-                size: 10,
+                price: Price(10010),
+                size: Qty(10),
                 side: Side::ASK,
                 order_type: OrderType::LIMIT,
+                ..Default::default()
             };
 
             lob.insert(raw_order);
@@ -301,8 +620,8 @@ mod tests {
         assert_eq!(lob.bid_map.len(), 0);
         assert_eq!(lob.ord_map.len(), 10);
 
-        let limit = lob.ask_map.get(&OrderedFloat(100.10)).unwrap();
-        assert_eq!(limit.borrow().vol, 100);
+        let limit = lob.ask_map.get(&Price(10010)).unwrap();
+        assert_eq!(limit.borrow().vol, Qty(100));
     }
 
     #[test]
@@ -313,10 +632,11 @@ mod tests {
                 seq_id: i,
                 order_id: format!("ORDER{:?}", i),
                 quote: "BTCETH".into(),
-                price: 100.10 + i as f64,
-                size: 10,
+                price: Price(10010 + i as i64 * 100),
+                size: Qty(10),
                 side: Side::ASK,
                 order_type: OrderType::LIMIT,
+                ..Default::default()
             };
 
             lob.insert(raw_order);
@@ -337,10 +657,11 @@ mod tests {
             seq_id: 1,
             order_id: "ORDER1".into(),
             quote: "BTCETH".into(),
-            price: 100.10,
-            size: 10,
+            price: Price(10010),
+            size: Qty(10),
             side: Side::ASK,
             order_type: OrderType::LIMIT,
+            ..Default::default()
         };
 
         lob.insert(raw_order);
@@ -352,14 +673,17 @@ mod tests {
         assert_eq!(lob.ord_map.len(), 1);
         assert!(lob.best_ask.is_some());
 
-        lob.remove("ORDER1".into());
+        let removed = lob.remove("ORDER1".into()).unwrap();
+        assert_eq!(removed.order_id, String::from("ORDER1"));
+        assert_eq!(removed.size, Qty(10));
 
         assert_eq!(lob.ask_list.len(), 0);
         assert_eq!(lob.bid_list.len(), 0);
         assert_eq!(lob.ask_map.len(), 0);
         assert_eq!(lob.bid_map.len(), 0);
         assert_eq!(lob.ord_map.len(), 0);
-        assert!(lob.best_ask.is_some());
+        assert!(lob.best_ask.is_none());
+        assert!(lob.remove("ORDER1".into()).is_none());
     }
 
     #[test]
@@ -371,10 +695,11 @@ mod tests {
                 seq_id: i,
                 order_id: format!("ORDER{:?}", i),
                 quote: "BTCETH".into(),
-                price: 100.10,
-                size: 10,
+                price: Price(10010),
+                size: Qty(10),
                 side: Side::ASK,
                 order_type: OrderType::LIMIT,
+                ..Default::default()
             };
 
             lob.insert(raw_order);
@@ -386,8 +711,8 @@ mod tests {
         assert_eq!(lob.bid_map.len(), 0);
         assert_eq!(lob.ord_map.len(), 10);
 
-        let limit = lob.ask_map.get(&OrderedFloat(100.10)).unwrap().clone();
-        assert_eq!(limit.borrow().vol, 100);
+        let limit = lob.ask_map.get(&Price(10010)).unwrap().clone();
+        assert_eq!(limit.borrow().vol, Qty(100));
 
         assert!(limit.borrow().head.is_some());
         let head_order = limit.borrow().head.clone().unwrap();
@@ -401,8 +726,277 @@ mod tests {
         assert_eq!(lob.bid_map.len(), 0);
         assert_eq!(lob.ord_map.len(), 9);
 
-        assert_eq!(limit.borrow().vol, 90);
+        assert_eq!(limit.borrow().vol, Qty(90));
         let head_order = limit.borrow().head.clone().unwrap();
         assert_eq!(head_order.borrow().order_id, String::from("ORDER1"));
     }
+
+    #[test]
+    fn removal_in_the_middle_and_the_tail() {
+        let mut lob = create_lob();
+
+        for i in 0..4 {
+            let raw_order = RawOrder {
+                seq_id: i,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(10010),
+                size: Qty(10),
+                side: Side::BID,
+                order_type: OrderType::LIMIT,
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+        let limit = lob.bid_map.get(&Price(10010)).unwrap().clone();
+
+        // removing from the middle links the neighbours together.
+        lob.remove("ORDER1".into());
+        let head_order = limit.borrow().head.clone().unwrap();
+        let next_order = head_order.borrow().next.clone().unwrap();
+        assert_eq!(next_order.borrow().order_id, String::from("ORDER2"));
+        let prev_order = next_order.borrow().prev.clone().unwrap().upgrade().unwrap();
+        assert_eq!(prev_order.borrow().order_id, String::from("ORDER0"));
+
+        // removing the tail makes the prev order the new tail.
+        lob.remove("ORDER3".into());
+        let tail_order = limit.borrow().tail.clone().unwrap();
+        assert_eq!(tail_order.borrow().order_id, String::from("ORDER2"));
+        assert!(tail_order.borrow().next.is_none());
+        assert_eq!(limit.borrow().vol, Qty(20));
+
+        // a new order is appended after the new tail.
+        let raw_order = RawOrder {
+            seq_id: 4,
+            order_id: "ORDER4".into(),
+            quote: "BTCETH".into(),
+            price: Price(10010),
+            size: Qty(10),
+            side: Side::BID,
+            order_type: OrderType::LIMIT,
+            ..Default::default()
+        };
+        lob.insert(raw_order);
+        let next_order = tail_order.borrow().next.clone().unwrap();
+        assert_eq!(next_order.borrow().order_id, String::from("ORDER4"));
+
+        lob.remove("ORDER0".into());
+        lob.remove("ORDER2".into());
+        lob.remove("ORDER4".into());
+        assert!(limit.borrow().head.is_none());
+        assert!(limit.borrow().tail.is_none());
+        assert_eq!(lob.bid_list.len(), 0);
+        assert!(lob.best_bid.is_none());
+    }
+
+    #[test]
+    fn best_order_follows_insert_and_remove() {
+        let mut lob = create_lob();
+
+        for (i, price) in [10020, 10010, 10030].iter().enumerate() {
+            let raw_order = RawOrder {
+                seq_id: i as u128,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(*price),
+                size: Qty(10),
+                side: Side::ASK,
+                order_type: OrderType::LIMIT,
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+
+        // the best ask is the lowest price, even if it was inserted later.
+        let best_ask = lob.best_ask.clone().unwrap();
+        assert_eq!(best_ask.borrow().order_id, String::from("ORDER1"));
+
+        lob.remove("ORDER1".into());
+        let best_ask = lob.best_ask.clone().unwrap();
+        assert_eq!(best_ask.borrow().order_id, String::from("ORDER0"));
+
+        // filling the best order moves to the next limit node as well.
+        assert_eq!(lob.execute("ORDER0", Qty(10)), Some(Qty::ZERO));
+        let best_ask = lob.best_ask.clone().unwrap();
+        assert_eq!(best_ask.borrow().order_id, String::from("ORDER2"));
+    }
+
+    #[test]
+    fn iceberg_replenishes_from_reserve() {
+        let mut lob = create_lob();
+
+        for i in 0..2 {
+            let raw_order = RawOrder {
+                seq_id: i,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(10010),
+                size: Qty(25),
+                side: Side::ASK,
+                order_type: OrderType::LIMIT,
+                display_size: if i == 0 { Some(Qty(10)) } else { None },
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+
+        // only the peak of the iceberg order is displayed.
+        let limit = lob.ask_map.get(&Price(10010)).unwrap().clone();
+        assert_eq!(lob.depth(Side::ASK, Price(10010)), Some(Qty(35)));
+        assert_eq!(limit.borrow().reserve, Qty(15));
+        assert_eq!(lob.liquidity(Side::ASK, None, Qty(100)), Qty(50));
+
+        // at most the peak is traded at once.
+        assert_eq!(lob.execute("ORDER0", Qty(12)), Some(Qty(15)));
+        assert_eq!(lob.depth(Side::ASK, Price(10010)), Some(Qty(35)));
+        assert_eq!(limit.borrow().reserve, Qty(5));
+
+        // the replenished peak moved behind the other order of the limit node.
+        let head_order = limit.borrow().head.clone().unwrap();
+        assert_eq!(head_order.borrow().order_id, String::from("ORDER1"));
+        let tail_order = limit.borrow().tail.clone().unwrap();
+        assert_eq!(tail_order.borrow().order_id, String::from("ORDER0"));
+        assert_eq!(tail_order.borrow().visible, Qty(10));
+        assert_eq!(tail_order.borrow().filled, Qty(10));
+        assert_eq!(tail_order.borrow().seq_id, 0);
+
+        // the last peak is smaller than the display size.
+        assert_eq!(lob.execute("ORDER0", Qty(10)), Some(Qty(5)));
+        assert_eq!(lob.depth(Side::ASK, Price(10010)), Some(Qty(30)));
+        assert_eq!(limit.borrow().reserve, Qty(0));
+
+        let removed = lob.remove("ORDER0".into()).unwrap();
+        assert_eq!(removed.size, Qty(5));
+        assert_eq!(lob.depth(Side::ASK, Price(10010)), Some(Qty(25)));
+    }
+
+    #[test]
+    fn snapshots_walk_both_sides_from_the_best_price() {
+        let mut lob = create_lob();
+
+        let orders = [
+            (Side::ASK, 10030, None),
+            (Side::ASK, 10020, Some(Qty(4))),
+            (Side::ASK, 10020, None),
+            (Side::BID, 10000, None),
+            (Side::BID, 10010, None),
+        ];
+        for (i, (side, price, display_size)) in orders.into_iter().enumerate() {
+            let raw_order = RawOrder {
+                seq_id: i as u128,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(price),
+                size: Qty(10),
+                side,
+                order_type: OrderType::LIMIT,
+                display_size,
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+
+        let snapshot = lob.l2_snapshot(10);
+        assert_eq!(snapshot.quote, String::from("LIMITORDERBOOK"));
+        let asks: Vec<_> = snapshot
+            .asks
+            .iter()
+            .map(|level| (level.price, level.vol, level.orders))
+            .collect();
+        assert_eq!(
+            asks,
+            vec![(Price(10020), Qty(14), 2), (Price(10030), Qty(10), 1)]
+        );
+        let bids: Vec<_> = snapshot.bids.iter().map(|level| level.price).collect();
+        assert_eq!(bids, vec![Price(10010), Price(10000)]);
+        assert_eq!(lob.l2_snapshot(1).asks.len(), 1);
+
+        // only the peak of the iceberg order is shown.
+        let snapshot = lob.l3_snapshot();
+        let asks: Vec<_> = snapshot
+            .asks
+            .iter()
+            .map(|order| (order.order_id.as_str(), order.size))
+            .collect();
+        assert_eq!(
+            asks,
+            vec![("ORDER1", Qty(4)), ("ORDER2", Qty(10)), ("ORDER0", Qty(10))]
+        );
+        let bids: Vec<_> = snapshot.bids.iter().map(|order| order.seq_id).collect();
+        assert_eq!(bids, vec![4, 3]);
+    }
+
+    #[test]
+    fn amend_keeps_priority_on_size_decrease() {
+        let mut lob = create_lob();
+
+        for i in 0..3 {
+            let raw_order = RawOrder {
+                seq_id: i,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(10010),
+                size: Qty(10),
+                side: Side::ASK,
+                order_type: OrderType::LIMIT,
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+
+        assert!(lob.amend("ORDER0", Price(10010), Qty(4)));
+
+        let limit = lob.ask_map.get(&Price(10010)).unwrap().clone();
+        assert_eq!(limit.borrow().vol, Qty(24));
+        let head_order = limit.borrow().head.clone().unwrap();
+        assert_eq!(head_order.borrow().order_id, String::from("ORDER0"));
+        assert_eq!(head_order.borrow().size, Qty(4));
+    }
+
+    #[test]
+    fn amend_loses_priority_on_size_increase_or_price_change() {
+        let mut lob = create_lob();
+
+        for i in 0..3 {
+            let raw_order = RawOrder {
+                seq_id: i,
+                order_id: format!("ORDER{:?}", i),
+                quote: "BTCETH".into(),
+                price: Price(10010),
+                size: Qty(10),
+                side: Side::ASK,
+                order_type: OrderType::LIMIT,
+                ..Default::default()
+            };
+
+            lob.insert(raw_order);
+        }
+
+        // the head order moves to the back of the queue.
+        assert!(lob.amend("ORDER0", Price(10010), Qty(15)));
+
+        let limit = lob.ask_map.get(&Price(10010)).unwrap().clone();
+        assert_eq!(limit.borrow().vol, Qty(35));
+        let head_order = limit.borrow().head.clone().unwrap();
+        assert_eq!(head_order.borrow().order_id, String::from("ORDER1"));
+        let tail_order = limit.borrow().tail.clone().unwrap();
+        assert_eq!(tail_order.borrow().order_id, String::from("ORDER0"));
+        assert_eq!(tail_order.borrow().seq_id, 0);
+
+        // the tail order moves to another limit node.
+        assert!(lob.amend("ORDER0", Price(10020), Qty(15)));
+
+        assert_eq!(lob.ask_list.len(), 2);
+        assert_eq!(limit.borrow().vol, Qty(20));
+        let tail_order = limit.borrow().tail.clone().unwrap();
+        assert_eq!(tail_order.borrow().order_id, String::from("ORDER2"));
+        assert_eq!(lob.depth(Side::ASK, Price(10020)), Some(Qty(15)));
+
+        assert!(!lob.amend("ORDER9", Price(10020), Qty(15)));
+    }
 }
@@ -1,11 +1,14 @@
 use std::{cell::RefCell, fmt::Debug, rc::Rc};
 
+use core_utils::{Price, Qty};
+
 use crate::order::Order;
 
 #[derive(Clone)]
 pub struct Limit {
-    pub price: f64,
-    pub vol: u64,
+    pub price: Price,
+    pub vol: Qty,     // displayed volume of the limit node.
+    pub reserve: Qty, // hidden volume of the iceberg orders of the limit node.
     pub head: Option<Rc<RefCell<Order>>>,
     pub tail: Option<Rc<RefCell<Order>>>,
 }
@@ -15,17 +18,24 @@ impl Debug for Limit {
         f.debug_struct("Limit Node")
             .field("price", &self.price)
             .field("volume", &self.vol)
+            .field("reserve", &self.reserve)
             .finish()
     }
 }
 
 impl Limit {
-    pub fn new(price: f64) -> Limit {
+    pub fn new(price: Price) -> Limit {
         Limit {
             price,
-            vol: 0,
+            vol: Qty::ZERO,
+            reserve: Qty::ZERO,
             head: None,
             tail: None,
         }
     }
+
+    /// Returns the orders of the limit node in queue order.
+    pub fn orders(&self) -> impl Iterator<Item = Rc<RefCell<Order>>> {
+        std::iter::successors(self.head.clone(), |order| order.borrow().next.clone())
+    }
 }
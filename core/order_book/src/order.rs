@@ -4,17 +4,26 @@ use std::{
     rc::{Rc, Weak},
 };
 
-use core_utils::{OrderType, RawOrder, Side};
+use core_utils::{
+    OrderType, PostOnly, Price, Qty, RawOrder, SelfTradePrevention, Side, TimeInForce,
+};
 
 #[derive(Clone)]
 pub struct Order {
     pub seq_id: u128,
     pub order_id: String,
     pub quote: String,
-    pub price: f64,
-    pub size: u64,
+    pub price: Price,
+    pub size: Qty,    // remaining size of the order, including the hidden reserve.
+    pub visible: Qty, // part of the remaining size shown in the limit node.
+    pub display_size: Option<Qty>, // peak of an iceberg order, `None` shows the whole size.
+    pub filled: Qty,  // cumulative quantity executed against the order.
     pub side: Side,
     pub order_type: OrderType,
+    pub time_in_force: TimeInForce, // kept so an order matched again after an amend still expires.
+    pub post_only: Option<PostOnly>,
+    pub owner_id: u64,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
     pub prev: Option<Weak<RefCell<Order>>>,
     pub next: Option<Rc<RefCell<Order>>>,
 }
@@ -27,8 +36,14 @@ impl Debug for Order {
             .field("quote", &self.quote)
             .field("price", &self.price)
             .field("size", &self.size)
+            .field("visible", &self.visible)
+            .field("display size", &self.display_size)
+            .field("filled", &self.filled)
             .field("side", &self.side)
             .field("type", &self.order_type)
+            .field("time in force", &self.time_in_force)
+            .field("post only", &self.post_only)
+            .field("owner ID", &self.owner_id)
             .finish()
     }
 }
@@ -41,8 +56,17 @@ impl From<RawOrder> for Order {
             quote: value.quote.to_owned(),
             price: value.price.to_owned(),
             size: value.size.to_owned(),
+            visible: value
+                .display_size
+                .map_or(value.size, |peak| peak.min(value.size)),
+            display_size: value.display_size.to_owned(),
+            filled: Qty::ZERO,
             side: value.side.to_owned(),
             order_type: value.order_type.to_owned(),
+            time_in_force: value.time_in_force.to_owned(),
+            post_only: value.post_only.to_owned(),
+            owner_id: value.owner_id.to_owned(),
+            self_trade_prevention: value.self_trade_prevention.to_owned(),
             prev: None,
             next: None,
         }
@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashMap};
+
+use core_utils::{Price, RawOrder, Side};
+
+/// Returns `true` when the last trade price reaches the stop price of an order of the given side,
+/// a buy stop triggers at or above its stop price and a sell stop at or below it.
+pub fn is_triggered(side: Side, stop_price: Price, last_price: Price) -> bool {
+    match side {
+        Side::BID => last_price >= stop_price,
+        Side::ASK => last_price <= stop_price,
+    }
+}
+
+/// This struct holds the stop orders which are waiting for their trigger, they are kept
+/// outside the limit order book so they are neither visible nor matched until released.
+#[derive(Debug, Default)]
+pub struct StopBook {
+    pub bid_stops: BTreeMap<(Price, u128), RawOrder>, // buy stops keyed by stop price and sequence id.
+    pub ask_stops: BTreeMap<(Price, u128), RawOrder>, // sell stops keyed by stop price and sequence id.
+    pub ord_map: HashMap<String, (Side, Price, u128)>, // hash map for fast lookups of the stop orders by order id.
+}
+
+impl StopBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ord_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ord_map.is_empty()
+    }
+
+    pub fn contains(&self, order_id: &str) -> bool {
+        self.ord_map.contains_key(order_id)
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&RawOrder> {
+        let (side, price, seq_id) = self.ord_map.get(order_id)?;
+        match side {
+            Side::BID => self.bid_stops.get(&(*price, *seq_id)),
+            Side::ASK => self.ask_stops.get(&(*price, *seq_id)),
+        }
+    }
+
+    /// Inserts a stop order, orders without a stop price are triggered by their own price.
+    pub fn insert(&mut self, raw_order: RawOrder) {
+        let key = (
+            raw_order.stop_price.unwrap_or(raw_order.price),
+            raw_order.seq_id,
+        );
+        self.ord_map
+            .insert(raw_order.order_id.clone(), (raw_order.side, key.0, key.1));
+        match raw_order.side {
+            Side::BID => self.bid_stops.insert(key, raw_order),
+            Side::ASK => self.ask_stops.insert(key, raw_order),
+        };
+    }
+
+    /// Removes the stop order and returns it, or `None` if the order does not exist.
+    pub fn remove(&mut self, order_id: &str) -> Option<RawOrder> {
+        let (side, price, seq_id) = self.ord_map.remove(order_id)?;
+        match side {
+            Side::BID => self.bid_stops.remove(&(price, seq_id)),
+            Side::ASK => self.ask_stops.remove(&(price, seq_id)),
+        }
+    }
+
+    /// Removes and returns every stop order triggered by the last trade price, in sequence order
+    /// so the matching engine releases them deterministically.
+    /// ```rust
+    /// use core_utils::{Price, RawOrder, Side};
+    ///
+    /// let mut stops = lob::stop::StopBook::new();
+    /// for (seq_id, stop_price, side) in [(1, 10100, Side::BID), (2, 10000, Side::BID), (3, 9900, Side::ASK)] {
+    ///     let raw_order = RawOrder::default()
+    ///         .with_seq_id(seq_id)
+    ///         .with_order_id(format!("STOP{seq_id}"))
+    ///         .with_side(side)
+    ///         .with_stop_price(Some(Price(stop_price)))
+    ///         .to_owned();
+    ///     stops.insert(raw_order);
+    /// }
+    ///
+    /// assert!(stops.triggered(Price(9950)).is_empty());
+    /// let released = stops.triggered(Price(10100));
+    /// assert_eq!(released.iter().map(|order| order.seq_id).collect::<Vec<_>>(), vec![1, 2]);
+    /// assert_eq!(stops.len(), 1);
+    /// assert_eq!(stops.triggered(Price(9900))[0].seq_id, 3);
+    /// ```
+    pub fn triggered(&mut self, last_price: Price) -> Vec<RawOrder> {
+        let mut keys: Vec<(Side, Price, u128)> = self
+            .bid_stops
+            .range(..=(last_price, u128::MAX))
+            .map(|(key, _)| (Side::BID, key.0, key.1))
+            .chain(
+                self.ask_stops
+                    .range((last_price, 0)..)
+                    .map(|(key, _)| (Side::ASK, key.0, key.1)),
+            )
+            .collect();
+        keys.sort_by_key(|(_, _, seq_id)| *seq_id);
+
+        let mut released = Vec::with_capacity(keys.len());
+        for (side, price, seq_id) in keys {
+            let stops = match side {
+                Side::BID => &mut self.bid_stops,
+                Side::ASK => &mut self.ask_stops,
+            };
+            if let Some(raw_order) = stops.remove(&(price, seq_id)) {
+                self.ord_map.remove(&raw_order.order_id);
+                released.push(raw_order);
+            }
+        }
+        released
+    }
+}
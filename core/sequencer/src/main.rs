@@ -1,7 +1,7 @@
+use crate::seq::Sequencer;
 use anyhow::{anyhow, Ok};
-use core_utils::OrderValue;
+use core_utils::{OrderValue, Price, Qty};
 use log::info;
-use crate::seq::Sequencer;
 
 pub mod seq;
 
@@ -9,10 +9,10 @@ fn get_quote() -> anyhow::Result<String> {
     let args = std::env::args().collect::<Vec<String>>();
 
     if args.len() == 2 {
-        let quote=args[1].clone();
-        return Ok(quote);
+        let quote = args[1].clone();
+        Ok(quote)
     } else {
-        return Err(anyhow!("Only one argument is required"));
+        Err(anyhow!("Only one argument is required"))
     }
 }
 
@@ -26,10 +26,19 @@ async fn main() -> anyhow::Result<()> {
     let order_value = OrderValue {
         order_id: "ORDER".into(),
         quote: "BTCETH".into(),
-        price: 100.10,
-        size: 10,
+        price: Price::from_decimal("100.10", 2)?,
+        size: Qty::from_decimal("10", 0)?,
         side: core_utils::Side::ASK,
         order_type: core_utils::OrderType::LIMIT,
+        protection_price: None,
+        time_in_force: core_utils::TimeInForce::GTC,
+        command: core_utils::Command::NEW,
+        post_only: None,
+        reduce_only: false,
+        display_size: None,
+        stop_price: None,
+        owner_id: 0,
+        self_trade_prevention: None,
     };
     unsafe { sequencer.inbound_manager.as_mut() }
         .unwrap()
@@ -1,19 +1,54 @@
-use std::mem::size_of;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Ok;
-use core_utils::{ExecuteMessage, OrderValue, RawOrder};
-use log::info;
-use memmap::MmapQueue;
+use core_utils::{ExecuteMessage, Execution, OrderValue, RawOrder, RejectReason};
+use log::{info, warn};
+use memmap::{
+    Backoff, Journal, JournalReader, MmapQueue, QueueMode, SeqCursor, SyncPolicy, WaitStrategy,
+};
 
 fn tmp_path(name: &str) -> std::path::PathBuf {
     std::env::temp_dir().join(format!("mmap_queue_{}.dat", name))
 }
 
-fn create_queue(path: &str, size: usize) -> anyhow::Result<MmapQueue> {
-    MmapQueue::create(tmp_path(path), 1024, size)
+/// Largest encoded message on the queues of the sequencer. The encoded size of an order
+/// depends on its order id, so it has nothing to do with the size of the struct in memory.
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+fn create_queue(path: &str, mode: QueueMode) -> anyhow::Result<MmapQueue> {
+    MmapQueue::create_with_mode(tmp_path(path), 1024, MAX_MESSAGE_SIZE, mode)
+}
+
+/// The queues to and from the matching engine are rings of records, every message only
+/// takes the space it needs. A ring left by a previous run is kept with its messages, the
+/// executions the engine committed before a restart are still forwarded.
+fn open_ring(path: &str) -> anyhow::Result<MmapQueue> {
+    MmapQueue::open_or_create_records(tmp_path(path), 1 << 20, MAX_MESSAGE_SIZE)
 }
 
-type WriteHeadLog = *mut MmapQueue; // used specially for a logging
+/// The sequencer clock, nanoseconds since the unix epoch. Every sequenced order is stamped
+/// with it so the matching engine can expire orders deterministically.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Answers a request that was not passed to the matching engine. Its sequence number is
+/// journaled with an empty payload, so it is never issued again after a restart.
+fn reject(
+    journal: &mut Journal,
+    outbound_manager: &mut MmapQueue,
+    seq: u128,
+    reason: RejectReason,
+) -> anyhow::Result<()> {
+    journal.append(seq, &[])?;
+    let rejected = ExecuteMessage::new(seq, Execution::REJECTED(reason));
+    warn!("{:?}", Event::Out(rejected.clone()));
+    outbound_manager.enqueue(&rejected.as_bytes())
+}
 
 #[derive(Debug)]
 pub enum Event {
@@ -26,37 +61,66 @@ pub struct Sequencer {
     pub inbound_engine: *mut MmapQueue,
     pub outbound_engine: *mut MmapQueue,
     pub inbound_manager: *mut MmapQueue,
-    pub write_head_log: WriteHeadLog,
+    pub journal: Journal, // every sequence number issued, the orders passed to the matching engine and the requests rejected before it.
     pub outbound_manager: *mut MmapQueue,
+    pub wait: WaitStrategy, // how the run loop waits once there is nothing to sequence or forward.
     seq: u128,
+    recovered: VecDeque<Vec<u8>>, // journaled orders the matching engine never processed, sent before any new order.
 }
 
 impl Sequencer {
     pub fn new(quote: &str) -> anyhow::Result<Self> {
-        let inbound_engine = create_queue(&format!("{}-inbound", quote), size_of::<RawOrder>())?;
-        let outbound_engine =
-            create_queue(&format!("{}-outbound", quote), size_of::<ExecuteMessage>())?;
-        let inbound_manager = create_queue(
-            &format!("{}-inbound-manager", quote),
-            size_of::<OrderValue>(),
-        )?;
-
-        let outbound_manager = create_queue(
-            &format!("{}-outbound-manager", quote),
-            size_of::<ExecuteMessage>(),
-        )?;
-
-        let write_head_log =
-            MmapQueue::create(format!("{}.orders.dat", quote), 4096, size_of::<RawOrder>())?;
+        let inbound_engine = open_ring(&format!("{}-inbound", quote))?;
+        let outbound_engine = open_ring(&format!("{}-outbound", quote))?;
+        // every order manager process writes its requests to the same queue.
+        let inbound_manager = create_queue(&format!("{}-inbound-manager", quote), QueueMode::MPSC)?;
+
+        // the executions are read by every order manager, risk and drop copy on their own.
+        let outbound_manager =
+            create_queue(&format!("{}-outbound-manager", quote), QueueMode::BROADCAST)?;
+
+        let journal_dir = format!("{}.journal", quote);
+        let journal = Journal::open(&journal_dir, 64 << 20, SyncPolicy::ALWAYS)?;
+        if journal.torn_bytes() > 0 {
+            warn!(
+                "cut {} bytes of a torn record off the journal of {quote}",
+                journal.torn_bytes()
+            );
+        }
+
+        // every journaled order after the last one the engine processed is sent again, whether
+        // it is still in the inbound ring or not, the engine skips the ones it already has.
+        // the engine keeps its cursor next to its snapshot, in the directory of the journal.
+        let processed = SeqCursor::open(format!("{}.progress", quote))?.load();
+        let mut recovered = VecDeque::new();
+        for record in JournalReader::open(&journal_dir, processed.map_or(0, |last| last + 1))? {
+            let (_, payload) = record?;
+            // the requests the sequencer rejected itself never reached the engine.
+            if !payload.is_empty() {
+                recovered.push_back(payload);
+            }
+        }
+        if !recovered.is_empty() {
+            info!("resending {} journaled orders of {quote}", recovered.len());
+        }
+
+        // the sequence carries on after the last journaled order or reject, and never reuses a
+        // sequence number the engine has already seen.
+        let seq = journal
+            .last_seq_id()
+            .max(processed)
+            .map_or(0, |last| last + 1);
 
         Ok(Sequencer {
             quote: quote.to_string(),
             inbound_engine: Box::into_raw(Box::new(inbound_engine)),
             outbound_engine: Box::into_raw(Box::new(outbound_engine)),
             inbound_manager: Box::into_raw(Box::new(inbound_manager)),
-            write_head_log: Box::into_raw(Box::new(write_head_log)),
+            journal,
             outbound_manager: Box::into_raw(Box::new(outbound_manager)),
-            seq: 0,
+            wait: WaitStrategy::SPIN_YIELD(1024),
+            seq,
+            recovered,
         })
     }
 
@@ -65,22 +129,91 @@ impl Sequencer {
         let outbound_manager = unsafe { self.outbound_manager.as_mut().unwrap() };
         let inbound_engine = unsafe { self.inbound_engine.as_mut().unwrap() };
         let outbound_engine = unsafe { self.outbound_engine.as_mut().unwrap() };
-        let event_mmap_log = unsafe { self.write_head_log.as_mut().unwrap() };
+        let mut backoff = Backoff::new(self.wait);
 
         loop {
-            if let Result::Ok(Some(v)) = inbound_manager.dequeue() {
-                let raw_order = bincode::deserialize::<OrderValue>(&v)?.into_raw(self.seq);
-                self.seq += 1;
-                let payload = bincode::serialize(&raw_order).unwrap();
-                event_mmap_log.enqueue(&payload)?;
-                info!("{:?}", Event::In(raw_order));
-                inbound_engine.enqueue(&payload)?;
+            let mut idle = true;
+            // the recovered orders keep their sequence numbers, so they go before any new order.
+            if let Some(payload) = self.recovered.front() {
+                if !inbound_engine.is_full() {
+                    inbound_engine.enqueue(payload)?;
+                    self.recovered.pop_front();
+                    idle = false;
+                }
             }
+            // new orders and cancel requests share the same sequence, so the engine
+            // applies a cancel exactly after the orders that were sequenced before it.
+            // A request is only taken once the engine ring has room for it, until then it
+            // waits in the queue of the order managers while the engine drains the ring.
+            else if !inbound_engine.is_full() {
+                if let Result::Ok(Some(v)) = inbound_manager.peek() {
+                    idle = false;
+                    backoff.reset();
+                    let seq = self.seq;
+                    self.seq += 1;
+
+                    // every request that can't reach the matching engine is answered with a
+                    // reject, so the order manager always learns what happened to it.
+                    let order_value = match bincode::deserialize::<OrderValue>(&v) {
+                        Result::Ok(order_value) => order_value,
+                        Err(_) => {
+                            reject(
+                                &mut self.journal,
+                                outbound_manager,
+                                seq,
+                                RejectReason::MALFORMED,
+                            )?;
+                            continue;
+                        }
+                    };
+                    if order_value.quote != self.quote {
+                        reject(
+                            &mut self.journal,
+                            outbound_manager,
+                            seq,
+                            RejectReason::UNKNOWN_INSTRUMENT,
+                        )?;
+                        continue;
+                    }
+
+                    let raw_order = order_value.into_raw(seq, now());
+                    let len = bincode::serialized_size(&raw_order)? as usize;
+                    if len > inbound_engine.max_payload_size() {
+                        reject(
+                            &mut self.journal,
+                            outbound_manager,
+                            seq,
+                            RejectReason::MALFORMED,
+                        )?;
+                        continue;
+                    }
 
-            if let Result::Ok(Some(v)) = outbound_engine.dequeue() {
+                    // the order is encoded straight into its slot and journaled from there,
+                    // before the matching engine can act on it.
+                    let mut slot = inbound_engine.reserve(len)?;
+                    bincode::serialize_into(&mut *slot, &raw_order)?;
+                    self.journal.append(seq, &slot)?;
+                    info!("{:?}", Event::In(raw_order));
+                    slot.commit();
+                }
+            }
+
+            if let Result::Ok(Some(v)) = outbound_engine.peek() {
                 let execute_msg = bincode::deserialize::<ExecuteMessage>(&v)?;
                 info!("{:?}", Event::Out(execute_msg));
                 outbound_manager.enqueue(&v)?;
+                idle = false;
+            }
+
+            if !idle {
+                backoff.reset();
+                continue;
+            }
+            // the journal syncs on append, an idle sequencer still syncs its last records.
+            self.journal.sync_if_due()?;
+            if backoff.snooze() {
+                // the sequencer reads two queues, it can't park on a single one of them.
+                std::thread::yield_now();
             }
         }
     }